@@ -0,0 +1,21 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+struct Afterimage {
+    intensity: f32,
+}
+
+@group(0) @binding(0) var accumulation: texture_2d<f32>;
+@group(0) @binding(1) var accumulation_sampler: sampler;
+@group(0) @binding(2) var<uniform> afterimage: Afterimage;
+
+// Blended by the fade constant alone, which scales down everything accumulated so far.
+@fragment
+fn fade_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(0.0);
+}
+
+// Shapes were alpha-blended onto a transparent buffer, leaving their colors premultiplied.
+@fragment
+fn composite_main(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    return textureSampleLevel(accumulation, accumulation_sampler, in.uv, 0.0) * afterimage.intensity;
+}
@@ -1,27 +1,151 @@
 #import bevy_render::view::View
 
+#ifdef INSTANCED
+struct VertexInput {
+    @location(0) mesh_position: vec2<f32>,
+    @location(1) corner: u32,
+    @location(2) position: vec2<f32>,
+    @location(3) rotation: vec2<f32>,
+    @location(4) size: vec2<f32>,
+    @location(5) color_0: vec4<f32>,
+    @location(6) color_1: vec4<f32>,
+    @location(7) color_2: vec4<f32>,
+    @location(8) color_3: vec4<f32>,
+    @location(9) pivot: vec2<f32>,
+    @location(10) animation: vec3<f32>,
+    @location(11) emissive: f32,
+}
+#else
 struct VertexInput {
     @location(0) position: vec2<f32>,
     @location(1) color: vec4<f32>,
+    @location(2) extrude: vec2<f32>,
+    @location(3) stroke: vec2<f32>,
+    @location(4) pivot: vec2<f32>,
+    @location(5) animation: vec3<f32>,
+    @location(6) emissive: f32,
 }
+#endif
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) color: vec4<f32>,
+    @location(1) emissive: f32,
+}
+
+struct Batch {
+    time: f32,
+    depth: f32,
+    tint: vec4<f32>,
+    params: vec4<f32>,
 }
 
 @group(0) @binding(0) var<uniform> view: View;
+@group(1) @binding(0) var<uniform> batch: Batch;
+
+// Positions are in world space, unless `SCREEN_PIXELS` or `SCREEN_NORMALIZED` is defined.
+fn clip_position(position: vec2<f32>) -> vec4<f32> {
+#ifdef SCREEN_PIXELS
+    let uv = position / view.viewport.zw;
+    return vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+#else ifdef SCREEN_NORMALIZED
+    return vec4<f32>(position.x * 2.0 - 1.0, 1.0 - position.y * 2.0, 0.0, 1.0);
+#else
+    return view.view_proj * vec4<f32>(position, 0.0, 1.0);
+#endif
+}
+
+// Cosine and sine of the angle `animation` (phase, amplitude, frequency) rotates by at the batch's time.
+fn animate(animation: vec3<f32>) -> vec2<f32> {
+    let angle = animation.y * sin(6.283185307 * animation.z * batch.time + animation.x);
+    return vec2<f32>(cos(angle), sin(angle));
+}
+
+fn rotate(v: vec2<f32>, rotation: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(v.x * rotation.x - v.y * rotation.y, v.x * rotation.y + v.y * rotation.x);
+}
+
+// Moves `clip` by `offset`, in world units or pixels as `stroke.x` says, scaled up to at least `stroke.y` pixels per
+// unit.
+fn extrude(position: vec2<f32>, clip: vec4<f32>, offset: vec2<f32>, stroke: vec2<f32>) -> vec4<f32> {
+    let extent = length(offset);
+    if extent <= 0.0 {
+        return clip;
+    }
+
+    // Pixel offsets only keep their length; their direction still goes through the view, which may be rotated.
+    let half_size = view.viewport.zw * 0.5;
+    var pixels = (clip_position(position + offset).xy - clip.xy) * half_size;
+    if stroke.x >= 0.5 {
+        pixels *= extent / max(length(pixels), 1e-6);
+    }
+
+    let scale = length(pixels) / extent;
+    if scale < stroke.y {
+        pixels *= stroke.y / max(scale, 1e-6);
+    }
+
+    return vec4<f32>(clip.xy + pixels / half_size * clip.w, clip.zw);
+}
 
 @vertex
 fn vertex_main(in: VertexInput) -> VertexOutput {
+#ifdef INSTANCED
+    let local = in.mesh_position * in.size;
+    let rotation = animate(in.animation);
+    let position = in.pivot + rotate(in.position + rotate(local, in.rotation) - in.pivot, rotation);
+
+    var colors = array<vec4<f32>, 4>(in.color_0, in.color_1, in.color_2, in.color_3);
+    let color = colors[in.corner];
+#else
+    let rotation = animate(in.animation);
+    let position = in.pivot + rotate(in.position - in.pivot, rotation);
+    let color = in.color;
+#endif
+
     var out: VertexOutput;
-    out.clip_position = view.view_proj * vec4<f32>(in.position, 0.0, 1.0);
-    out.color = in.color;
+    out.clip_position = clip_position(position);
+#ifndef INSTANCED
+    out.clip_position = extrude(position, out.clip_position, rotate(in.extrude, rotation), in.stroke);
+#endif
+#ifdef OPAQUE
+    out.clip_position.z = batch.depth * out.clip_position.w;
+#endif
+    out.color = color;
+    out.emissive = in.emissive;
 
     return out;
 }
 
+fn hash(p: vec2<f32>) -> f32 {
+    return fract(sin(dot(p, vec2<f32>(12.9898, 78.233))) * 43758.5453);
+}
+
 @fragment
 fn fragment_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return in.color;
+    var color = in.color * batch.tint;
+#ifdef HDR
+    // Applied after interpolation, so glowing colors blend like their base ones; LDR targets would only clamp it.
+    color = vec4<f32>(color.rgb * in.emissive, color.a);
+#endif
+
+#ifdef PULSE
+    color.a *= 1.0 - batch.params.w * (0.5 + 0.5 * sin(batch.time * batch.params.x * 6.2831855));
+#endif
+#ifdef SCANLINES
+    if fract(in.clip_position.y / batch.params.y) < 0.5 {
+        color = vec4<f32>(color.rgb * (1.0 - batch.params.w), color.a);
+    }
+#endif
+#ifdef DISSOLVE
+    if hash(floor(in.clip_position.xy)) < batch.params.z {
+        discard;
+    }
+#endif
+#ifdef DEBUG_OVERDRAW
+    // Blended additively, so every layer drawn over a pixel heats it up.
+    color = vec4<f32>(0.08, 0.03, 0.01, 1.0);
+#endif
+
+    return color;
 }
@@ -0,0 +1,91 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+struct PostProcess {
+    vignette_color: vec4<f32>,
+    vignette: vec4<f32>,
+    grain: vec4<f32>,
+    scanlines: vec4<f32>,
+    aberration: f32,
+    lut_intensity: f32,
+    lut_size: f32,
+    time: f32,
+}
+
+@group(0) @binding(0) var source: texture_2d<f32>;
+@group(0) @binding(1) var source_sampler: sampler;
+@group(0) @binding(2) var<uniform> post: PostProcess;
+@group(0) @binding(3) var lut: texture_2d<f32>;
+@group(0) @binding(4) var lut_sampler: sampler;
+
+fn hash(p: vec2<f32>) -> f32 {
+    return fract(sin(dot(p, vec2<f32>(12.9898, 78.233))) * 43758.5453);
+}
+
+// Colors are linear here even on LDR targets, which only encode them as sRGB when written.
+fn to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let c = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+    return select(1.055 * pow(c, vec3<f32>(1.0 / 2.4)) - 0.055, c * 12.92, c <= vec3<f32>(0.0031308));
+}
+
+fn to_linear(c: vec3<f32>) -> vec3<f32> {
+    return select(pow((c + 0.055) / 1.055, vec3<f32>(2.4)), c / 12.92, c <= vec3<f32>(0.04045));
+}
+
+// Looks `color` up in the LUT strip, blending between the two blue slices it falls between.
+fn grade(color: vec3<f32>) -> vec3<f32> {
+    let size = post.lut_size;
+    let srgb = to_srgb(color);
+
+    let blue = srgb.b * (size - 1.0);
+    let slice = floor(blue);
+    let texel = (srgb.rg * (size - 1.0) + 0.5) / size;
+
+    let next = min(slice + 1.0, size - 1.0);
+    let lower = textureSampleLevel(lut, lut_sampler, vec2<f32>((slice + texel.x) / size, texel.y), 0.0).rgb;
+    let upper = textureSampleLevel(lut, lut_sampler, vec2<f32>((next + texel.x) / size, texel.y), 0.0).rgb;
+    return mix(color, to_linear(mix(lower, upper, blue - slice)), post.lut_intensity);
+}
+
+@fragment
+fn fragment_main(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    var uv = in.uv;
+    let size = vec2<f32>(textureDimensions(source));
+
+#ifdef CRT_SCANLINES
+    // Barrel distortion; whatever it pushes off the screen is black.
+    let centered = uv * 2.0 - 1.0;
+    uv = centered * (1.0 + post.scanlines.z * dot(centered, centered)) * 0.5 + 0.5;
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+#endif
+
+    var color = textureSampleLevel(source, source_sampler, uv, 0.0);
+#ifdef CHROMATIC_ABERRATION
+    let offset = (uv - 0.5) * 2.0 * post.aberration;
+    color.r = textureSampleLevel(source, source_sampler, uv + offset, 0.0).r;
+    color.b = textureSampleLevel(source, source_sampler, uv - offset, 0.0).b;
+#endif
+#ifdef COLOR_LUT
+    color = vec4<f32>(grade(color.rgb), color.a);
+#endif
+#ifdef CRT_SCANLINES
+    let line = 0.5 + 0.5 * cos(6.2831855 * uv.y * size.y / post.scanlines.x);
+    color = vec4<f32>(color.rgb * (1.0 - post.scanlines.y * line), color.a);
+#endif
+#ifdef VIGNETTE
+    // Distance from the center, `1.0` at the middle of the edges.
+    let dist = length(uv * 2.0 - 1.0);
+    let amount = smoothstep(post.vignette.y, post.vignette.y + post.vignette.z, dist) * post.vignette.x;
+    color = vec4<f32>(mix(color.rgb, post.vignette_color.rgb, amount), color.a);
+#endif
+#ifdef FILM_GRAIN
+    // Reseeded per frame of grain, kept small so `hash` stays precise.
+    let frame = floor(post.time * post.grain.z);
+    let seed = fract(vec2<f32>(frame * 0.1031, frame * 0.1307)) * 1000.0;
+    let noise = hash(floor(in.position.xy / post.grain.y) + seed) - 0.5;
+    color = vec4<f32>(color.rgb * (1.0 + noise * 2.0 * post.grain.x), color.a);
+#endif
+
+    return color;
+}
@@ -0,0 +1,119 @@
+#import bevy_render::view::View
+
+struct VertexInput {
+    @location(0) center: vec2<f32>,
+    @location(1) rotation: vec2<f32>,
+    @location(2) local: vec2<f32>,
+    @location(3) shape: vec4<f32>,
+    @location(4) inner: vec4<f32>,
+    @location(5) outer: vec4<f32>,
+    @location(6) emissive: f32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) local: vec2<f32>,
+    @location(1) shape: vec4<f32>,
+    @location(2) inner: vec4<f32>,
+    @location(3) outer: vec4<f32>,
+    @location(4) emissive: f32,
+}
+
+struct Batch {
+    time: f32,
+    depth: f32,
+    tint: vec4<f32>,
+    params: vec4<f32>,
+}
+
+@group(0) @binding(0) var<uniform> view: View;
+@group(1) @binding(0) var<uniform> batch: Batch;
+
+// Same coordinate spaces as `draw.wgsl`.
+fn clip_position(position: vec2<f32>) -> vec4<f32> {
+#ifdef SCREEN_PIXELS
+    let uv = position / view.viewport.zw;
+    return vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+#else ifdef SCREEN_NORMALIZED
+    return vec4<f32>(position.x * 2.0 - 1.0, 1.0 - position.y * 2.0, 0.0, 1.0);
+#else
+    return view.view_proj * vec4<f32>(position, 0.0, 1.0);
+#endif
+}
+
+// Length of a pixel in the units positions are in.
+fn pixel_size() -> f32 {
+#ifdef SCREEN_PIXELS
+    return 1.0;
+#else ifdef SCREEN_NORMALIZED
+    return 1.0 / view.viewport.z;
+#else
+    return 2.0 / (length(view.view_proj[0].xy) * view.viewport.z);
+#endif
+}
+
+fn rotate(v: vec2<f32>, rotation: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(v.x * rotation.x - v.y * rotation.y, v.x * rotation.y + v.y * rotation.x);
+}
+
+// Signed distance to a box of `half_size` whose corners are rounded by `radius`.
+fn rounded_box(p: vec2<f32>, half_size: vec2<f32>, radius: f32) -> f32 {
+    let q = abs(p) - half_size + radius;
+    return length(max(q, vec2<f32>(0.0))) + min(max(q.x, q.y), 0.0) - radius;
+}
+
+@vertex
+fn vertex_main(in: VertexInput) -> VertexOutput {
+    // Grows the quad by a pixel, so the anti-aliased fringe isn't cut off.
+    let local = in.local + sign(in.local) * pixel_size();
+
+    var out: VertexOutput;
+    out.clip_position = clip_position(in.center + rotate(local, in.rotation));
+#ifdef OPAQUE
+    out.clip_position.z = batch.depth * out.clip_position.w;
+#endif
+    out.local = local;
+    out.shape = in.shape;
+    out.inner = in.inner;
+    out.outer = in.outer;
+    out.emissive = in.emissive;
+
+    return out;
+}
+
+@fragment
+fn fragment_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let half_size = in.shape.xy;
+    let thickness = in.shape.w;
+
+    // Filled shapes shade from their center to their edge, outlines across their thickness.
+    var dist = rounded_box(in.local, half_size, in.shape.z);
+    var reach = min(half_size.x, half_size.y);
+    if thickness > 0.0 {
+        reach = thickness;
+    }
+
+    let edge = clamp(1.0 + dist / max(reach, 1e-6), 0.0, 1.0);
+    if thickness > 0.0 {
+        dist = abs(dist + thickness * 0.5) - thickness * 0.5;
+    }
+
+    let coverage = clamp(0.5 - dist / max(fwidth(dist), 1e-6), 0.0, 1.0);
+    var color = mix(in.inner, in.outer, edge) * batch.tint;
+#ifdef HDR
+    color = vec4<f32>(color.rgb * in.emissive, color.a);
+#endif
+
+#ifdef OPAQUE
+    if coverage < 0.5 {
+        discard;
+    }
+#else
+    color.a *= coverage;
+#endif
+#ifdef DEBUG_OVERDRAW
+    color = vec4<f32>(0.08, 0.03, 0.01, 1.0);
+#endif
+
+    return color;
+}
@@ -2,10 +2,14 @@ use bevy::prelude::*;
 
 use crate::{
     draw::{
-        vertex::{DrawKey, DrawVertex},
+        vertex::{DrawInstance, DrawKey, DrawVertex, Stroke},
         Drawer,
     },
-    shape::vertex::Request,
+    shape::{
+        primitive::Primitive,
+        sdf::SdfShape,
+        vertex::{InstanceRequest, Request, Topology},
+    },
     util::math::{sqrt, vec_angle},
 };
 
@@ -19,8 +23,9 @@ impl<'a> Drawer<'a> {
         (x3, y3, col3): (f32, f32, Color),
         (x4, y4, col4): (f32, f32, Color),
     ) {
-        self.requests.push(Request {
+        self.push(Request {
             layer,
+            topology: Topology::TriangleList,
             vertices: vec![
                 DrawVertex::new(x1, y1, col1),
                 DrawVertex::new(x2, y2, col2),
@@ -40,8 +45,9 @@ impl<'a> Drawer<'a> {
         (x2, y2, col2): (f32, f32, Color),
         (x3, y3, col3): (f32, f32, Color),
     ) {
-        self.requests.push(Request {
+        self.push(Request {
             layer,
+            topology: Topology::TriangleList,
             vertices: vec![
                 DrawVertex::new(x1, y1, col1),
                 DrawVertex::new(x2, y2, col2),
@@ -52,14 +58,87 @@ impl<'a> Drawer<'a> {
         });
     }
 
+    /// A quad of `stroke`, each corner given by its point on the center line, its extrusion from it and its color.
+    pub fn stroke_quad(&mut self, key: DrawKey, layer: f32, stroke: Stroke, corners: [(Vec2, Vec2, Color); 4]) {
+        self.push(Request {
+            layer,
+            topology: Topology::TriangleList,
+            vertices: corners
+                .map(|(center, extrude, color)| DrawVertex::stroke(center.x, center.y, color, extrude, stroke))
+                .into(),
+            indices: vec![0, 1, 2, 2, 3, 0],
+            key,
+        });
+    }
+
+    /// A triangle strip through `points`, needing one index per point instead of three per triangle.
+    pub fn strip(&mut self, key: DrawKey, layer: f32, points: impl IntoIterator<Item = (f32, f32, Color)>) {
+        let vertices = points
+            .into_iter()
+            .map(|(x, y, color)| DrawVertex::new(x, y, color))
+            .collect::<Vec<_>>();
+
+        if vertices.len() < 3 {
+            return
+        }
+
+        self.push(Request {
+            layer,
+            topology: Topology::TriangleStrip,
+            indices: (0..vertices.len() as u32).collect(),
+            vertices,
+            key,
+        });
+    }
+
+    /// A line one pixel wide, regardless of zoom.
+    pub fn hairline(
+        &mut self,
+        key: DrawKey,
+        layer: f32,
+        (x1, y1, col1): (f32, f32, Color),
+        (x2, y2, col2): (f32, f32, Color),
+    ) {
+        self.push(Request {
+            layer,
+            topology: Topology::LineList,
+            vertices: vec![DrawVertex::new(x1, y1, col1), DrawVertex::new(x2, y2, col2)],
+            indices: vec![0, 1],
+            key,
+        });
+    }
+
+    #[inline]
+    pub fn instance(&mut self, key: DrawKey, layer: f32, primitive: Primitive, instance: DrawInstance) {
+        self.push_instance(InstanceRequest {
+            layer,
+            primitive,
+            instance,
+            key,
+        });
+    }
+
     pub fn tri_angle(&mut self, state: TriState, layer: f32, x: f32, y: f32, angle: f32) {
         let TriState {
             key,
             width,
             length,
             colors,
+            instanced,
         } = state;
 
+        if instanced {
+            let [base, tip] = colors;
+            self.instance(
+                key,
+                layer,
+                Primitive::Tri,
+                DrawInstance::new(x, y, angle, length, width, [base, base, tip, tip]),
+            );
+
+            return
+        }
+
         let Vec2 { x: mut x2, y: mut y2 } = vec_angle(angle, length, 0.0);
         x2 += x;
         y2 += y;
@@ -80,6 +159,27 @@ impl<'a> Drawer<'a> {
             (x2, y2, colors[1]),
         );
     }
+
+    #[inline]
+    pub fn circle(&mut self, state: CircleState, layer: f32, x: f32, y: f32, radius: f32) {
+        let CircleState {
+            key,
+            colors: [center, edge],
+            sdf,
+        } = state;
+
+        if sdf {
+            self.sdf(key, layer, SdfShape::circle(Vec2::new(x, y), radius), center, edge);
+            return
+        }
+
+        self.instance(
+            key,
+            layer,
+            Primitive::Circle,
+            DrawInstance::new(x, y, 0.0, radius, radius, [center, edge, edge, edge]),
+        );
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -88,6 +188,8 @@ pub struct TriState {
     pub width: f32,
     pub length: f32,
     pub colors: [Color; 2],
+    /// Draws as an instance of [`Primitive::Tri`] instead of its own vertices.
+    pub instanced: bool,
 }
 
 impl TriState {
@@ -109,6 +211,12 @@ impl TriState {
         self.colors = [from, to];
         self
     }
+
+    #[inline]
+    pub fn instanced(mut self) -> Self {
+        self.instanced = true;
+        self
+    }
 }
 
 impl Default for TriState {
@@ -119,6 +227,47 @@ impl Default for TriState {
             width: 1.0,
             length: 1.0,
             colors: [Color::WHITE; 2],
+            instanced: false,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct CircleState {
+    pub key: DrawKey,
+    pub colors: [Color; 2],
+    /// Draws as an anti-aliased [`SdfShape`] instead of an instance of [`Primitive::Circle`]; requires the
+    /// [`SdfPlugin`](crate::shape::sdf::SdfPlugin).
+    pub sdf: bool,
+}
+
+impl CircleState {
+    #[inline]
+    pub fn color(mut self, color: Color) -> Self {
+        self.colors = [color; 2];
+        self
+    }
+
+    #[inline]
+    pub fn color_edge(mut self, center: Color, edge: Color) -> Self {
+        self.colors = [center, edge];
+        self
+    }
+
+    #[inline]
+    pub fn sdf(mut self) -> Self {
+        self.sdf = true;
+        self
+    }
+}
+
+impl Default for CircleState {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            key: default(),
+            colors: [Color::WHITE; 2],
+            sdf: false,
         }
     }
 }
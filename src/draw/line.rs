@@ -1,7 +1,11 @@
 use bevy::prelude::*;
 
 use crate::{
-    draw::{vertex::DrawKey, Drawer},
+    draw::{
+        vertex::{DrawInstance, DrawKey, Stroke, StrokeUnits},
+        Drawer,
+    },
+    shape::{primitive::Primitive, sdf::SdfShape},
     util::{
         math::{equal, sin, sqrt, vec_angle, Interp::Linear, Interpolation},
         FloatExt, VecExt,
@@ -10,9 +14,25 @@ use crate::{
 
 impl<'a> Drawer<'a> {
     pub fn line(&mut self, state: LineState, layer: f32, x_from: f32, y_from: f32, x_to: f32, y_to: f32) {
-        let LineState { key, stroke, colors } = state;
+        let LineState {
+            key, colors, instanced, ..
+        } = state;
+
+        // Instances can't be extruded per view, so only world-unit strokes are drawn as such.
+        let stroke = state.to_stroke();
+        if instanced && stroke.is_world() {
+            let (dx, dy) = (x_to - x_from, y_to - y_from);
+            self.instance(
+                key,
+                layer,
+                Primitive::Quad,
+                DrawInstance::new(x_from, y_from, dy.atan2(dx), sqrt(dx * dx + dy * dy), stroke.width, colors),
+            );
 
-        let hs = stroke / 2.0;
+            return
+        }
+
+        let hs = stroke.width / 2.0;
         let mut dx = x_to - x_from;
         let mut dy = y_to - y_from;
         let len = sqrt(dx * dx + dy * dy);
@@ -20,14 +40,13 @@ impl<'a> Drawer<'a> {
         dx = dx / len * hs;
         dy = dy / len * hs;
 
-        self.quad(
-            key,
-            layer,
-            (x_from - dy, y_from + dx, colors[0]),
-            (x_from + dy, y_from - dx, colors[1]),
-            (x_to + dy, y_to - dx, colors[2]),
-            (x_to - dy, y_to + dx, colors[3]),
-        );
+        let (from, to) = (Vec2::new(x_from, y_from), Vec2::new(x_to, y_to));
+        self.stroke_quad(key, layer, stroke, [
+            (from, Vec2::new(-dy, dx), colors[0]),
+            (from, Vec2::new(dy, -dx), colors[1]),
+            (to, Vec2::new(dy, -dx), colors[2]),
+            (to, Vec2::new(-dy, dx), colors[3]),
+        ]);
     }
 
     #[inline]
@@ -36,8 +55,15 @@ impl<'a> Drawer<'a> {
         self.line(state, layer, x, y, x + to.x, y + to.y);
     }
 
+    /// A ring whose stroke is centered on `radius`. With [`LineState::sdf`] and a stroke in world units, it's drawn
+    /// analytically from the inner color of its first segment to the outer one instead, ignoring `segments`.
     pub fn line_circle(&mut self, state: LineState, layer: f32, x: f32, y: f32, radius: f32, segments: usize) {
-        let LineState { key, stroke, colors } = state;
+        let LineState { key, colors, sdf, .. } = state;
+        if sdf && state.to_stroke().is_world() {
+            let shape = SdfShape::ring(Vec2::new(x, y), radius + state.stroke / 2.0, state.stroke);
+            self.sdf(key, layer, shape, colors[0], colors[1]);
+            return
+        }
 
         let mut lines = self.lines();
         for i in (0..segments).map(|i| i as f32) {
@@ -53,7 +79,7 @@ impl<'a> Drawer<'a> {
             );
         }
 
-        lines.flush(key, stroke, true);
+        lines.flush(key, state.to_stroke(), true);
     }
 
     #[inline]
@@ -81,7 +107,7 @@ impl<'t, 'a> Lines<'t, 'a> {
         self.points.push((x, y, layer, left_color, right_color));
     }
 
-    pub fn flush(self, key: DrawKey, stroke: f32, wrap: bool) {
+    pub fn flush(self, key: DrawKey, stroke: Stroke, wrap: bool) {
         // Taken from https://github.com/earlygrey/shapedrawer/blob/master/drawer/src/space/earlygrey/shapedrawer/ShapeDrawer.java.
         let points = self.points;
         if points.len() < 2 {
@@ -89,10 +115,10 @@ impl<'t, 'a> Lines<'t, 'a> {
         }
 
         let drawer = self.drawer;
-        let hw = stroke * 0.5;
+        let hw = stroke.width * 0.5;
         let len = points.len();
 
-        drawer.requests.reserve(len);
+        drawer.shapes.requests.reserve(len);
 
         let straight = |a: Vec2, b: Vec2| {
             let ab = b - a;
@@ -127,14 +153,13 @@ impl<'t, 'a> Lines<'t, 'a> {
             (Vec2::new(v.y, -v.x) + b, Vec2::new(-v.y, v.x) + b)
         };
 
-        let mut push = |layer: f32, p1: Vec2, c1: Color, p2: Vec2, c2: Color, p3: Vec2, c3: Color, p4: Vec2, c4: Color| {
-            drawer.quad(
+        // Corners are given as their point on the center line, their position, and their color.
+        let mut push = |layer: f32, corners: [(Vec2, Vec2, Color); 4]| {
+            drawer.stroke_quad(
                 key,
                 layer,
-                (p1.x, p1.y, c1),
-                (p2.x, p2.y, c2),
-                (p3.x, p3.y, c3),
-                (p4.x, p4.y, c4),
+                stroke,
+                corners.map(|(center, corner, color)| (center, corner - center, color)),
             );
         };
 
@@ -157,17 +182,12 @@ impl<'t, 'a> Lines<'t, 'a> {
                 p2 = left;
             }
 
-            push(
-                (points[i - 1].2 + points[i - 1].2) / 2.0,
-                p1,
-                points[i - 1].3,
-                p2,
-                points[i - 1].4,
-                p3,
-                points[i].4,
-                p4,
-                points[i].3,
-            );
+            push((points[i - 1].2 + points[i - 1].2) / 2.0, [
+                (a, p1, points[i - 1].3),
+                (a, p2, points[i - 1].4),
+                (b, p3, points[i].4),
+                (b, p4, points[i].3),
+            ]);
 
             p1 = p4;
             p2 = p3;
@@ -179,36 +199,27 @@ impl<'t, 'a> Lines<'t, 'a> {
                     join(b, c, Vec2::new(points[0].0, points[0].1))
                 };
 
-                push(
-                    (points[i].2 + points[i].2) / 2.0,
-                    p1,
-                    points[i].3,
-                    p2,
-                    points[i].4,
-                    p3,
-                    points[i + 1].4,
-                    p4,
-                    points[i + 1].3,
-                );
+                push((points[i].2 + points[i].2) / 2.0, [
+                    (b, p1, points[i].3),
+                    (b, p2, points[i].4),
+                    (c, p3, points[i + 1].4),
+                    (c, p4, points[i + 1].3),
+                ]);
 
                 if wrap {
+                    let first = Vec2::new(points[0].0, points[0].1);
                     let (p1, p2) = join(
                         Vec2::new(points[len - 1].0, points[len - 1].1),
-                        Vec2::new(points[0].0, points[0].1),
+                        first,
                         Vec2::new(points[1].0, points[1].1),
                     );
 
-                    push(
-                        (points[i + 1].2 + points[0].2) / 2.0,
-                        p4,
-                        points[i].3,
-                        p3,
-                        points[i].4,
-                        p1,
-                        points[i + 1].4,
-                        p2,
-                        points[i + 1].3,
-                    )
+                    push((points[i + 1].2 + points[0].2) / 2.0, [
+                        (c, p4, points[i].3),
+                        (c, p3, points[i].4),
+                        (first, p1, points[i + 1].4),
+                        (first, p2, points[i + 1].3),
+                    ])
                 }
             }
         }
@@ -219,7 +230,15 @@ impl<'t, 'a> Lines<'t, 'a> {
 pub struct LineState {
     pub key: DrawKey,
     pub stroke: f32,
+    pub units: StrokeUnits,
+    /// See [`Stroke::min_pixels`].
+    pub min_pixels: f32,
     pub colors: [Color; 4],
+    /// Draws single lines as instances of [`Primitive::Quad`] instead of their own vertices, unless their width is in
+    /// pixels or has a minimum.
+    pub instanced: bool,
+    /// See [`Drawer::line_circle`]; requires the [`SdfPlugin`](crate::shape::sdf::SdfPlugin).
+    pub sdf: bool,
 }
 
 impl LineState {
@@ -229,6 +248,31 @@ impl LineState {
         self
     }
 
+    /// A stroke `width` physical pixels wide, regardless of zoom.
+    #[inline]
+    pub fn stroke_pixels(mut self, width: f32) -> Self {
+        self.stroke = width;
+        self.units = StrokeUnits::Pixels;
+        self
+    }
+
+    /// Keeps the stroke at least `min_pixels` physical pixels wide, e.g. `1.0` for outlines that stay visible when
+    /// zoomed out.
+    #[inline]
+    pub fn min_pixels(mut self, min_pixels: f32) -> Self {
+        self.min_pixels = min_pixels;
+        self
+    }
+
+    #[inline]
+    pub fn to_stroke(self) -> Stroke {
+        Stroke {
+            width: self.stroke,
+            units: self.units,
+            min_pixels: self.min_pixels,
+        }
+    }
+
     #[inline]
     pub fn color(mut self, color: Color) -> Self {
         self.colors = [color; 4];
@@ -246,6 +290,18 @@ impl LineState {
         self.colors = [left, right, right, left];
         self
     }
+
+    #[inline]
+    pub fn instanced(mut self) -> Self {
+        self.instanced = true;
+        self
+    }
+
+    #[inline]
+    pub fn sdf(mut self) -> Self {
+        self.sdf = true;
+        self
+    }
 }
 
 impl Default for LineState {
@@ -254,7 +310,11 @@ impl Default for LineState {
         Self {
             key: default(),
             stroke: 1.0,
+            units: StrokeUnits::World,
+            min_pixels: 0.0,
             colors: [Color::WHITE; 4],
+            instanced: false,
+            sdf: false,
         }
     }
 }
@@ -1,16 +1,100 @@
-use crate::{draw::vertex::DrawVertex, shape::vertex::Request};
+use bevy::prelude::*;
+
+use crate::{
+    draw::vertex::{DrawAnimation, DrawKey, DrawVertex},
+    shape::{
+        sdf::SdfShape,
+        vertex::{DrawSpace, InstanceRequest, Request, Shapes},
+    },
+};
 
 pub mod basic;
 pub mod line;
 pub mod vertex;
 
 pub struct Drawer<'a> {
-    requests: &'a mut Vec<Request<DrawVertex>>,
+    shapes: &'a mut Shapes<DrawVertex>,
+    space: Option<DrawSpace>,
+    animation: DrawAnimation,
+    emissive: f32,
 }
 
 impl<'a> Drawer<'a> {
     #[inline]
-    pub fn new(requests: &'a mut Vec<Request<DrawVertex>>) -> Self {
-        Self { requests }
+    pub fn new(shapes: &'a mut Shapes<DrawVertex>) -> Self {
+        Self {
+            shapes,
+            space: None,
+            animation: DrawAnimation::NONE,
+            emissive: 1.0,
+        }
+    }
+
+    /// Draws everything in `space`, regardless of the space of the [`DrawKey`]s passed in.
+    #[inline]
+    pub fn space(mut self, space: DrawSpace) -> Self {
+        self.space = Some(space);
+        self
+    }
+
+    /// Animates everything drawn from now on, until set back to [`DrawAnimation::NONE`].
+    #[inline]
+    pub fn set_animation(&mut self, animation: DrawAnimation) {
+        self.animation = animation;
+    }
+
+    /// Multiplies the [`emissive`](DrawVertex::emissive) intensity of everything drawn from now on, until set back to
+    /// `1.0`. Colors stay in `0.0..=1.0`, so they blend and interpolate the same regardless of how much they glow.
+    #[inline]
+    pub fn set_emissive(&mut self, emissive: f32) {
+        self.emissive = emissive;
+    }
+
+    #[inline]
+    fn key(&self, key: DrawKey) -> DrawKey {
+        match self.space {
+            None => key,
+            Some(space) => key.space(space),
+        }
+    }
+
+    fn push(&mut self, mut request: Request<DrawVertex>) {
+        request.key = self.key(request.key);
+        if !self.animation.is_none() {
+            for vertex in &mut request.vertices {
+                vertex.animation = self.animation;
+            }
+        }
+        if self.emissive != 1.0 {
+            for vertex in &mut request.vertices {
+                vertex.emissive *= self.emissive;
+            }
+        }
+
+        self.shapes.requests.push(request);
+    }
+
+    /// Draws `shape` analytically, shaded from `inner` to `outer`, through the
+    /// [`SdfPlugin`](crate::shape::sdf::SdfPlugin). Animations don't apply to it.
+    #[inline]
+    pub fn sdf(&mut self, key: DrawKey, layer: f32, shape: SdfShape, inner: Color, outer: Color) {
+        let key = self.key(key).sdf_key();
+        let mut request = shape.request(key, layer, inner, outer);
+        for vertex in &mut request.vertices {
+            vertex.emissive *= self.emissive;
+        }
+
+        self.shapes.sdf.push(request);
+    }
+
+    #[inline]
+    fn push_instance(&mut self, mut request: InstanceRequest<DrawVertex>) {
+        request.key = self.key(request.key);
+        if !self.animation.is_none() {
+            request.instance.animation = self.animation;
+        }
+        request.instance.emissive *= self.emissive;
+
+        self.shapes.instances.push(request);
     }
 }
@@ -5,33 +5,202 @@ use bevy::{
     prelude::*,
     render::render_resource::{
         BlendComponent, BlendFactor, BlendOperation, BlendState, BufferAddress, ColorWrites, RenderPipelineDescriptor,
-        VertexAttribute, VertexFormat,
+        ShaderDefVal, VertexAttribute, VertexFormat,
     },
 };
 
-use crate::shape::vertex::{Vertex, VertexKey};
+use crate::{
+    shape::{
+        material::ShapeMaterial,
+        primitive::Primitive,
+        sdf::SdfKey,
+        uniform::{BatchUniform, UniformParams},
+        vertex::{DrawSpace, Vertex, VertexKey},
+    },
+    util::math::vec_angle,
+};
 
 #[repr(C)]
 #[derive(Pod, Zeroable, Copy, Clone)]
 pub struct DrawVertex {
     pub position: [f32; 2],
+    /// Linear RGBA, the space the GPU interpolates in and render targets expect, sRGB ones included; see
+    /// [`DrawVertex::color()`].
     pub color: [f32; 4],
+    /// Offset from `position` the vertex shader moves the vertex by, scaled per view as set by `stroke`. Zero for
+    /// anything but strokes.
+    pub extrude: [f32; 2],
+    /// [`Stroke::attribute`] of the stroke the vertex belongs to.
+    pub stroke: [f32; 2],
+    pub animation: DrawAnimation,
+    /// Multiplier of `color`'s RGB applied after interpolation, pushing it past `1.0` for bloom; ignored by views that
+    /// aren't HDR.
+    pub emissive: f32,
 }
 
 impl DrawVertex {
+    /// Converts `color` to vertex colors, from whichever space it's in.
+    #[inline]
+    pub fn color(color: Color) -> [f32; 4] {
+        color.as_linear_rgba_f32()
+    }
+
     #[inline]
     pub fn new(x: f32, y: f32, color: Color) -> Self {
         Self {
             position: [x, y],
-            color: color.as_rgba_f32(),
+            color: Self::color(color),
+            extrude: [0.0; 2],
+            stroke: [0.0; 2],
+            animation: DrawAnimation::NONE,
+            emissive: 1.0,
+        }
+    }
+
+    /// A vertex of `stroke`, at `extrude` away from its center line at `x, y`.
+    #[inline]
+    pub fn stroke(x: f32, y: f32, color: Color, extrude: Vec2, stroke: Stroke) -> Self {
+        Self {
+            position: [x, y],
+            color: Self::color(color),
+            extrude: extrude.to_array(),
+            stroke: stroke.attribute(),
+            animation: DrawAnimation::NONE,
+            emissive: 1.0,
+        }
+    }
+}
+
+/// Periodic rotation around `pivot` evaluated in the vertex shader, by `amplitude * sin(TAU * frequency * time + phase)`
+/// radians with `time` taken from the batch uniform. Animated geometry stays the same across frames, so shapers
+/// drawing it can be [`Retained`](crate::shape::retain::Retained).
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone, PartialEq, Debug, Default)]
+pub struct DrawAnimation {
+    pub pivot: [f32; 2],
+    /// In radians.
+    pub phase: f32,
+    /// In radians.
+    pub amplitude: f32,
+    /// In hertz.
+    pub frequency: f32,
+}
+
+impl DrawAnimation {
+    pub const NONE: Self = Self {
+        pivot: [0.0; 2],
+        phase: 0.0,
+        amplitude: 0.0,
+        frequency: 0.0,
+    };
+
+    #[inline]
+    pub fn new(pivot: Vec2, phase: f32, amplitude: f32, frequency: f32) -> Self {
+        Self {
+            pivot: pivot.to_array(),
+            phase,
+            amplitude,
+            frequency,
+        }
+    }
+
+    #[inline]
+    pub fn is_none(&self) -> bool {
+        self.amplitude == 0.0
+    }
+}
+
+/// Units of stroke widths.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub enum StrokeUnits {
+    /// Scaled by the camera like everything else.
+    #[default]
+    World,
+    /// Physical pixels, regardless of zoom.
+    Pixels,
+}
+
+/// Width of a stroke, extruded from its center line in the vertex shader so that it can be kept legible per view.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Stroke {
+    pub width: f32,
+    pub units: StrokeUnits,
+    /// Width in physical pixels the stroke never gets thinner than, so it doesn't vanish when zoomed out; `0.0` for
+    /// none.
+    pub min_pixels: f32,
+}
+
+impl Stroke {
+    /// The units as `0.0` or `1.0`, and the pixels per unit of extrusion the stroke is scaled up to.
+    #[inline]
+    pub fn attribute(self) -> [f32; 2] {
+        let units = match self.units {
+            StrokeUnits::World => 0.0,
+            StrokeUnits::Pixels => 1.0,
+        };
+
+        let min_scale = if self.min_pixels > 0.0 && self.width > 0.0 {
+            self.min_pixels / self.width
+        } else {
+            0.0
+        };
+
+        [units, min_scale]
+    }
+
+    /// Whether the stroke only depends on world positions, so its vertices can be bounded on the CPU.
+    #[inline]
+    pub fn is_world(self) -> bool {
+        self.units == StrokeUnits::World && self.min_pixels <= 0.0
+    }
+}
+
+impl Default for Stroke {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            width: 1.0,
+            units: StrokeUnits::World,
+            min_pixels: 0.0,
+        }
+    }
+}
+
+/// Per-instance data of [`Primitive`](crate::shape::primitive::Primitive) meshes, transforming them by
+/// `rotation * size + position` and coloring their corners.
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone)]
+pub struct DrawInstance {
+    pub position: [f32; 2],
+    /// Cosine and sine of the rotation angle.
+    pub rotation: [f32; 2],
+    pub size: [f32; 2],
+    /// Linear RGBA, like [`DrawVertex::color`](DrawVertex#structfield.color).
+    pub colors: [[f32; 4]; 4],
+    pub animation: DrawAnimation,
+    /// See [`DrawVertex::emissive`].
+    pub emissive: f32,
+}
+
+impl DrawInstance {
+    #[inline]
+    pub fn new(x: f32, y: f32, angle: f32, width: f32, height: f32, colors: [Color; 4]) -> Self {
+        let Vec2 { x: cos, y: sin } = vec_angle(angle, 1.0, 0.0);
+        Self {
+            position: [x, y],
+            rotation: [cos, sin],
+            size: [width, height],
+            colors: colors.map(DrawVertex::color),
+            animation: DrawAnimation::NONE,
+            emissive: 1.0,
         }
     }
 }
 
 impl Vertex for DrawVertex {
     type Key = DrawKey;
+    type Instance = DrawInstance;
 
-    const SHADER: Handle<Shader> = Handle::weak_from_u128(213808777024918471717406675324426180314);
     const SHADER_SOURCE: &'static str = "shaders/draw.wgsl";
 
     const LAYOUT: &'static [VertexAttribute] = &[
@@ -45,13 +214,128 @@ impl Vertex for DrawVertex {
             offset: size_of::<[f32; 2]>() as BufferAddress,
             shader_location: 1,
         },
+        VertexAttribute {
+            format: VertexFormat::Float32x2,
+            offset: (size_of::<[f32; 2]>() + size_of::<[f32; 4]>()) as BufferAddress,
+            shader_location: 2,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x2,
+            offset: (size_of::<[[f32; 2]; 2]>() + size_of::<[f32; 4]>()) as BufferAddress,
+            shader_location: 3,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x2,
+            offset: (size_of::<[[f32; 2]; 3]>() + size_of::<[f32; 4]>()) as BufferAddress,
+            shader_location: 4,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x3,
+            offset: (size_of::<[[f32; 2]; 4]>() + size_of::<[f32; 4]>()) as BufferAddress,
+            shader_location: 5,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32,
+            offset: (size_of::<[[f32; 2]; 4]>() + size_of::<[f32; 4]>() + size_of::<[f32; 3]>()) as BufferAddress,
+            shader_location: 6,
+        },
+    ];
+
+    const INSTANCE_LAYOUT: &'static [VertexAttribute] = &[
+        VertexAttribute {
+            format: VertexFormat::Float32x2,
+            offset: 0,
+            shader_location: 2,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x2,
+            offset: size_of::<[f32; 2]>() as BufferAddress,
+            shader_location: 3,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x2,
+            offset: size_of::<[[f32; 2]; 2]>() as BufferAddress,
+            shader_location: 4,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x4,
+            offset: size_of::<[[f32; 2]; 3]>() as BufferAddress,
+            shader_location: 5,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x4,
+            offset: (size_of::<[[f32; 2]; 3]>() + size_of::<[f32; 4]>()) as BufferAddress,
+            shader_location: 6,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x4,
+            offset: (size_of::<[[f32; 2]; 3]>() + size_of::<[[f32; 4]; 2]>()) as BufferAddress,
+            shader_location: 7,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x4,
+            offset: (size_of::<[[f32; 2]; 3]>() + size_of::<[[f32; 4]; 3]>()) as BufferAddress,
+            shader_location: 8,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x2,
+            offset: (size_of::<[[f32; 2]; 3]>() + size_of::<[[f32; 4]; 4]>()) as BufferAddress,
+            shader_location: 9,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x3,
+            offset: (size_of::<[[f32; 2]; 4]>() + size_of::<[[f32; 4]; 4]>()) as BufferAddress,
+            shader_location: 10,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32,
+            offset: (size_of::<[[f32; 2]; 4]>() + size_of::<[[f32; 4]; 4]>() + size_of::<[f32; 3]>()) as BufferAddress,
+            shader_location: 11,
+        },
     ];
+
+    /// Strokes scaled per view and animated vertices can't be bounded on the CPU.
+    #[inline]
+    fn position(&self) -> Option<Vec2> {
+        (self.stroke == [0.0; 2] && self.animation.is_none())
+            .then(|| Vec2::from_array(self.position) + Vec2::from_array(self.extrude))
+    }
+
+    /// Primitive meshes reach at most `size` away from their origin. Animated instances aren't bounded.
+    #[inline]
+    fn instance_bounds(instance: &Self::Instance, _primitive: Primitive) -> Option<Rect> {
+        if !instance.animation.is_none() {
+            return None
+        }
+
+        let reach = Vec2::from_array(instance.size).abs().length();
+        Some(Rect::from_center_half_size(
+            Vec2::from_array(instance.position),
+            Vec2::splat(reach),
+        ))
+    }
+}
+
+/// Fragment effects evaluated on the GPU, each enabled through a shader def.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Default)]
+pub struct DrawEffects {
+    pub pulse: bool,
+    pub scanlines: bool,
+    pub dissolve: bool,
 }
 
+/// `params` of the batch uniform go: pulse frequency in hertz, scanline period in pixels, dissolve threshold, and the
+/// strength of pulses and scanlines.
 #[derive(Copy, Clone, Eq, PartialEq, Hash)]
 pub struct DrawKey {
     pub mask: ColorWrites,
     pub blend: Option<BlendState>,
+    pub effects: DrawEffects,
+    pub params: UniformParams,
+    pub material: Option<AssetId<ShapeMaterial>>,
+    /// See [`DrawKey::opaque`].
+    pub opaque: bool,
+    pub space: DrawSpace,
 }
 
 impl DrawKey {
@@ -67,8 +351,82 @@ impl DrawKey {
                 },
                 alpha: BlendComponent::OVER,
             }),
+            effects: DrawEffects {
+                pulse: false,
+                scanlines: false,
+                dissolve: false,
+            },
+            params: UniformParams::DEFAULT,
+            material: None,
+            opaque: false,
+            space: DrawSpace::World,
         }
     }
+
+    /// Draws in `space`, e.g. screen space for HUDs and cursors unaffected by the camera's pan and zoom.
+    #[inline]
+    pub fn space(mut self, space: DrawSpace) -> Self {
+        self.space = space;
+        self
+    }
+
+    /// Draws depth-tested without blending, beneath every transparent shape; meant for solid terrain and walls, which
+    /// then don't overdraw each other.
+    #[inline]
+    pub fn opaque(mut self) -> Self {
+        self.blend = None;
+        self.opaque = true;
+        self
+    }
+
+    /// The key drawing [`SdfVertex`](crate::shape::sdf::SdfVertex) shapes the same way, as far as they support it:
+    /// effects and materials are left out.
+    #[inline]
+    pub fn sdf_key(&self) -> SdfKey {
+        SdfKey {
+            mask: self.mask,
+            blend: self.blend,
+            params: self.params,
+            opaque: self.opaque,
+            space: self.space,
+        }
+    }
+
+    #[inline]
+    pub fn material(mut self, material: &Handle<ShapeMaterial>) -> Self {
+        self.material = Some(material.id());
+        self
+    }
+
+    #[inline]
+    pub fn tint(mut self, tint: Color) -> Self {
+        self.params = self.params.with_tint(Vec4::from_array(tint.as_linear_rgba_f32()));
+        self
+    }
+
+    #[inline]
+    pub fn pulse(mut self, frequency: f32, strength: f32) -> Self {
+        let params = self.params.params();
+        self.effects.pulse = true;
+        self.params = self.params.with_params(Vec4::new(frequency, params.y, params.z, strength));
+        self
+    }
+
+    #[inline]
+    pub fn scanlines(mut self, period: f32, strength: f32) -> Self {
+        let params = self.params.params();
+        self.effects.scanlines = true;
+        self.params = self.params.with_params(Vec4::new(params.x, period, params.z, strength));
+        self
+    }
+
+    #[inline]
+    pub fn dissolve(mut self, threshold: f32) -> Self {
+        let params = self.params.params();
+        self.effects.dissolve = true;
+        self.params = self.params.with_params(Vec4::new(params.x, params.y, threshold, params.w));
+        self
+    }
 }
 
 impl Default for DrawKey {
@@ -77,17 +435,125 @@ impl Default for DrawKey {
         Self {
             mask: ColorWrites::ALL,
             blend: Some(BlendState::ALPHA_BLENDING),
+            effects: default(),
+            params: default(),
+            material: None,
+            opaque: false,
+            space: default(),
         }
     }
 }
 
 impl VertexKey for DrawKey {
     fn specialize(self, desc: &mut RenderPipelineDescriptor) {
-        for target in &mut desc.fragment.as_mut().unwrap().targets {
-            if let Some(target) = target {
-                target.write_mask = self.mask;
-                target.blend = self.blend;
-            }
+        for target in desc
+            .fragment
+            .iter_mut()
+            .flat_map(|fragment| fragment.targets.iter_mut().flatten())
+        {
+            target.write_mask = self.mask;
+            target.blend = self.blend;
+        }
+    }
+
+    fn shader_defs(&self) -> Vec<ShaderDefVal> {
+        let DrawEffects {
+            pulse,
+            scanlines,
+            dissolve,
+        } = self.effects;
+
+        [(pulse, "PULSE"), (scanlines, "SCANLINES"), (dissolve, "DISSOLVE")]
+            .into_iter()
+            .chain(self.space.shader_def().map(|def| (true, def)))
+            .filter(|&(enabled, _)| enabled)
+            .map(|(_, def)| def.into())
+            .collect()
+    }
+
+    #[inline]
+    fn uniform(&self) -> BatchUniform {
+        self.params.uniform()
+    }
+
+    #[inline]
+    fn material(&self) -> Option<AssetId<ShapeMaterial>> {
+        self.material
+    }
+
+    #[inline]
+    fn opaque(&self) -> bool {
+        self.opaque
+    }
+
+    #[inline]
+    fn world_space(&self) -> bool {
+        self.space == DrawSpace::World
+    }
+
+    /// Materials only need to be told apart by their shader, which the renderer keys pipelines by.
+    #[inline]
+    fn pipeline_key(&self) -> Self {
+        Self {
+            params: UniformParams::DEFAULT,
+            material: None,
+            ..*self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use crate::{
+        draw::vertex::{DrawInstance, DrawVertex},
+        util::math::{within, Interp::Linear, Interpolation, Oklab},
+    };
+
+    fn assert_close(a: [f32; 4], b: [f32; 4]) {
+        assert!(a.iter().zip(b).all(|(&a, b)| within(a, b, 1e-4)), "{a:?} != {b:?}");
+    }
+
+    #[test]
+    fn srgb_round_trips_through_vertices() {
+        for color in [
+            Color::rgba(0.5, 0.25, 1.0, 0.5),
+            Color::hex("#edcb4fff").unwrap(),
+            Color::hsl(200.0, 0.7, 0.3),
+        ] {
+            let [red, green, blue, alpha] = DrawVertex::new(0.0, 0.0, color).color;
+            assert_close(Color::rgba_linear(red, green, blue, alpha).as_rgba_f32(), color.as_rgba_f32());
+        }
+    }
+
+    #[test]
+    fn interpolated_colors_stay_linear() {
+        // Already linear, so storing it must not convert it again.
+        let color = Linear.interp(Color::RED, Color::BLUE, 0.5);
+        assert_close(DrawVertex::new(0.0, 0.0, color).color, [0.5, 0.0, 0.5, 1.0]);
+
+        let from = Color::hex("#bd14c1ff").unwrap();
+        let to = Color::hex("#e92f70ff").unwrap();
+        for interp in [Linear.interp(from, to, 0.0), Oklab(Linear).interp(from, to, 0.0)] {
+            assert_close(DrawVertex::color(interp), DrawVertex::color(from));
+        }
+        for interp in [Linear.interp(from, to, 1.0), Oklab(Linear).interp(from, to, 1.0)] {
+            assert_close(DrawVertex::color(interp), DrawVertex::color(to));
+        }
+    }
+
+    #[test]
+    fn instances_match_vertices() {
+        let colors = [
+            Color::RED,
+            Color::rgba(0.2, 0.4, 0.6, 0.8),
+            Color::hsl(90.0, 0.5, 0.5),
+            Color::NONE,
+        ];
+        let instance = DrawInstance::new(0.0, 0.0, 0.0, 1.0, 1.0, colors);
+        for (color, instance) in colors.into_iter().zip(instance.colors) {
+            assert_close(instance, DrawVertex::new(0.0, 0.0, color).color);
         }
     }
 }
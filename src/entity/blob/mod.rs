@@ -1,6 +1,6 @@
 use bevy::{
     ecs::system::{
-        lifetimeless::{Read, SCommands, SQuery, SRes},
+        lifetimeless::{SCommands, SQuery},
         StaticSystemParam, SystemParamItem,
     },
     prelude::*,
@@ -10,11 +10,19 @@ use fastrand::Rng;
 use float_next_after::NextAfter;
 
 use crate::{
-    draw::{basic::TriState, line::LineState, vertex::DrawVertex, Drawer},
-    shape::vertex::{Request, Shaper},
+    draw::{
+        basic::TriState,
+        line::LineState,
+        vertex::{DrawAnimation, DrawVertex},
+        Drawer,
+    },
+    shape::{
+        retain::Retained,
+        vertex::{Shaper, Shapes},
+    },
     util::{
         math::{
-            curve, sin, vec_angle,
+            curve, vec_angle,
             Interp::{Linear, PowIn},
             Interpolation,
         },
@@ -27,51 +35,55 @@ pub struct Blob {
     pub border_color: Color,
     pub eye_color: Color,
     pub cell_color: Color,
+    /// Emissive intensities of each color, keeping the colors themselves in LDR. Parts mixing two colors mix their
+    /// intensities the same way.
+    pub border_glow: f32,
+    pub eye_glow: f32,
+    pub cell_glow: f32,
 }
 
 #[derive(Component, Copy, Clone)]
 pub struct BlobShaper {
     pub id: u64,
     pub trns: GlobalTransform,
-    pub time: f32,
     pub blob: Blob,
 }
 
 impl Shaper for BlobShaper {
     type ExtractParam = (
         SCommands,
-        Extract<'static, 'static, SRes<Time<Virtual>>>,
-        Extract<'static, 'static, SQuery<(Entity, Read<GlobalTransform>, Read<Blob>)>>,
+        Extract<'static, 'static, SQuery<(Entity, Ref<'static, GlobalTransform>, Ref<'static, Blob>)>>,
     );
     type DrawParam = ();
     type Vertex = DrawVertex;
 
     fn extract(param: StaticSystemParam<Self::ExtractParam>) {
-        let (mut commands, time, blobs) = param.into_inner();
-        let time = time.elapsed_seconds_wrapped();
-
-        for (e, &trns, &blob) in &blobs {
-            commands.spawn(BlobShaper {
-                id: e.to_bits(),
-                trns,
-                time,
-                blob,
-            });
+        let (mut commands, blobs) = param.into_inner();
+        for (e, trns, blob) in &blobs {
+            // The tendrils wobble in the vertex shader, so the geometry stays the same until the blob itself changes.
+            let changed = trns.is_changed() || blob.is_changed();
+            commands.spawn((
+                BlobShaper {
+                    id: e.to_bits(),
+                    trns: *trns,
+                    blob: *blob,
+                },
+                Retained::new(e, changed),
+            ));
         }
     }
 
     #[inline]
-    fn draw(&mut self, _: &mut SystemParamItem<Self::DrawParam>, out: &mut Vec<Request<Self::Vertex>>) {
-        let Self {
-            id,
-            trns,
-            time,
-            blob: Blob {
-                border_color,
-                eye_color,
-                cell_color,
-            },
-        } = *self;
+    fn draw(&mut self, _: &SystemParamItem<Self::DrawParam>, out: &mut Shapes<Self::Vertex>) {
+        let Self { id, trns, blob } = *self;
+        let Blob {
+            border_color,
+            eye_color,
+            cell_color,
+            border_glow,
+            eye_glow,
+            cell_glow,
+        } = blob;
 
         let (pos, mut layer) = trns.translation().separate_z();
         let mut draw = Drawer::new(out);
@@ -79,7 +91,7 @@ impl Shaper for BlobShaper {
 
         let r = 200.0;
         for i in (0..32).map(|i| i as f32) {
-            let origin = i / 32.0 * 360f32.to_radians();
+            let angle = i / 32.0 * 360f32.to_radians();
             let deviate = rng.range_f32(0.0, 4.0).to_radians();
             let move_scl = rng.range_f32(32.0, 64.0).to_radians();
             let start = rng.range_f32(0.25, 0.4) * r;
@@ -87,14 +99,18 @@ impl Shaper for BlobShaper {
             let width = rng.range_f32(0.05, 0.08) * r;
             let alpha = rng.range_f32(0.1, 0.6);
 
-            let angle = origin + sin((time * 120.0).to_radians() + i * move_scl, move_scl, deviate);
+            draw.set_animation(wobble(pos, i, move_scl, deviate, 120f32.to_radians()));
+            draw.set_emissive(PowIn(2).interp(cell_glow, eye_glow, curve(alpha, 0.3, 0.6)));
             let offset = vec_angle(angle, start, 0.0);
 
             draw.line_angle(
-                LineState::default().stroke(width).color_tip(
-                    PowIn(2).interp(cell_color, eye_color, curve(alpha, 0.1, 0.7)).with_a(0.0) * 0.4,
-                    PowIn(2).interp(cell_color, eye_color, curve(alpha, 0.3, 0.6)).with_a(alpha),
-                ),
+                LineState::default()
+                    .stroke(width)
+                    .color_tip(
+                        PowIn(2).interp(cell_color, eye_color, curve(alpha, 0.1, 0.7)).with_a(0.0) * 0.4,
+                        PowIn(2).interp(cell_color, eye_color, curve(alpha, 0.3, 0.6)).with_a(alpha),
+                    )
+                    .instanced(),
                 layer.next_swap(),
                 pos.x + offset.x,
                 pos.y + offset.y,
@@ -104,21 +120,24 @@ impl Shaper for BlobShaper {
         }
 
         for i in (0..24).map(|i| i as f32) {
-            let origin = rng.range_f32(0.0, 360.0.next_after(f32::NEG_INFINITY));
+            let angle = rng.range_f32(0.0, 360.0.next_after(f32::NEG_INFINITY));
             let deviate = rng.range_f32(0.0, 60.0).to_radians();
             let move_scl = rng.range_f32(24.0, 48.0).to_radians();
             let len = rng.range_f32(0.2, 0.5) * r;
             let width = rng.range_f32(0.1, 0.18) * r;
             let alpha = rng.range_f32(0.3, 0.6);
 
-            let angle = origin + sin((time * 60.0).to_radians() + i * move_scl, move_scl, deviate);
+            draw.set_animation(wobble(pos, i, move_scl, deviate, 60f32.to_radians()));
             let offset = vec_angle(angle, r, 0.0);
-            let color = Linear.interp(border_color, cell_color, 0.5 - curve(alpha, 0.3, 0.6) * 0.5);
+            let mix = 0.5 - curve(alpha, 0.3, 0.6) * 0.5;
+            let color = Linear.interp(border_color, cell_color, mix);
+            draw.set_emissive(Linear.interp(border_glow, cell_glow, mix));
 
             draw.tri_angle(
                 TriState::default()
                     .size(width, len)
-                    .color_tip(color.with_a(0.0), color.with_a(alpha)),
+                    .color_tip(color.with_a(0.0), color.with_a(alpha))
+                    .instanced(),
                 layer.next_swap(),
                 pos.x + offset.x,
                 pos.y + offset.y,
@@ -126,6 +145,8 @@ impl Shaper for BlobShaper {
             );
         }
 
+        draw.set_animation(DrawAnimation::NONE);
+        draw.set_emissive(border_glow);
         draw.line_circle(
             LineState::default()
                 .stroke(0.09 * r)
@@ -163,3 +184,9 @@ impl Shaper for BlobShaper {
          */
     }
 }
+
+/// Rotation around `pivot` by `sin((time * speed + i * scl) * scl) * mag`.
+#[inline]
+fn wobble(pivot: Vec2, i: f32, scl: f32, mag: f32, speed: f32) -> DrawAnimation {
+    DrawAnimation::new(pivot, i * scl * scl, mag, speed * scl / f32::PI2)
+}
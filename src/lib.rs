@@ -7,11 +7,13 @@ use iyes_progress::ProgressPlugin;
 use crate::{
     draw::vertex::DrawVertex,
     entity::{blob::Blob, EntityPlugin},
-    shape::ShapePlugin,
+    post::{ChromaticAberration, ColorLut, CrtScanlines, FilmGrain, PostProcessPlugin, Vignette},
+    shape::{debug::ShapeDebug, ShapePlugin},
 };
 
 pub mod draw;
 pub mod entity;
+pub mod post;
 pub mod shape;
 pub mod util;
 
@@ -31,15 +33,23 @@ pub fn entry() {
             ProgressPlugin::new(GameState::InitInternal)
                 .continue_to(GameState::Init)
                 .track_assets(),
-            ShapePlugin::<DrawVertex>::default(),
+            ShapePlugin::<DrawVertex>::default().track_loading(GameState::InitInternal),
+            PostProcessPlugin::default().track_loading(GameState::InitInternal),
             EntityPlugin,
         ))
         .insert_resource(ClearColor(Color::BLACK))
         .add_systems(OnEnter(GameState::Init), init)
+        .add_systems(Update, toggle_debug.run_if(|| cfg!(feature = "dev")))
         .run()
 }
 
-pub fn init(mut commands: Commands) {
+pub fn init(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    // Slightly desaturated, with teal shadows like under a microscope's lamp.
+    let lut = images.add(ColorLut::bake(32, |color| {
+        let luma = color.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+        color.lerp(Vec3::splat(luma), 0.15) + Vec3::new(-0.02, 0.03, 0.05) * (1.0 - luma)
+    }));
+
     commands.spawn((
         Camera2dBundle {
             camera: Camera { hdr: true, ..default() },
@@ -49,11 +59,34 @@ pub fn init(mut commands: Commands) {
             intensity: 0.5,
             ..BloomSettings::NATURAL
         },
+        Vignette::default(),
+        ChromaticAberration::default(),
+        FilmGrain::default(),
+        CrtScanlines::default(),
+        ColorLut::new(lut),
     ));
 
     commands.spawn((TransformBundle::default(), Blob {
-        border_color: Color::hex("#edcb4fff").unwrap() * 3.0,
-        eye_color: Color::hex("#e92f70ff").unwrap() * 4.5,
-        cell_color: Color::hex("#bd14c1ff").unwrap() * 1.5,
+        border_color: Color::hex("#edcb4fff").unwrap(),
+        eye_color: Color::hex("#e92f70ff").unwrap(),
+        cell_color: Color::hex("#bd14c1ff").unwrap(),
+        border_glow: 3.0,
+        eye_glow: 4.5,
+        cell_glow: 1.5,
     }));
 }
+
+/// F1 toggles wireframes, F2 batch tints, and F3 the overdraw heatmap.
+pub fn toggle_debug(input: Res<ButtonInput<KeyCode>>, mut debug: ResMut<ShapeDebug>) {
+    let ShapeDebug {
+        wireframe,
+        batch_tint,
+        overdraw,
+    } = &mut *debug;
+
+    for (key, flag) in [(KeyCode::F1, wireframe), (KeyCode::F2, batch_tint), (KeyCode::F3, overdraw)] {
+        if input.just_pressed(key) {
+            *flag = !*flag;
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use bevy::{
+    core_pipeline::core_2d::{
+        graph::{Core2d, Node2d},
+        Camera2d,
+    },
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_graph::{RenderGraphApp, ViewNodeRunner},
+        render_resource::{Extent3d, SpecializedRenderPipelines, TextureDimension, TextureFormat},
+        Extract, ExtractSchedule, Render, RenderApp, RenderSet,
+    },
+};
+
+use crate::{
+    post::pipeline::{
+        prepare_post_process, PostProcessEffects, PostProcessNode, PostProcessPass, PostProcessPipeline, PostProcessShader,
+        PostProcessUniform, PostProcessUniforms,
+    },
+    util::TrackLoading,
+};
+
+pub mod pipeline;
+
+/// Full-screen effects applied to 2D cameras after tonemapping, each enabled by adding its component to the camera:
+/// [`Vignette`], [`ChromaticAberration`], [`FilmGrain`], [`CrtScanlines`], and [`ColorLut`]. Cameras with none of them
+/// skip the pass.
+///
+/// They run in a single pass, in the order the shader applies them: scanline curvature warps the image, aberration
+/// splits its channels, the LUT grades it, then scanlines, vignette, and grain darken and noise it.
+#[derive(Default)]
+pub struct PostProcessPlugin {
+    track_loading: TrackLoading,
+}
+
+impl PostProcessPlugin {
+    /// Adds the shader to [`AssetsLoading`](iyes_progress::prelude::AssetsLoading) when entering `state`, so an
+    /// [`iyes_progress`] loading state waits for it.
+    #[inline]
+    pub fn track_loading(mut self, state: impl States) -> Self {
+        self.track_loading = TrackLoading::default().shader(state, |shader: &PostProcessShader| &shader.handle);
+        self
+    }
+}
+
+impl Plugin for PostProcessPlugin {
+    fn build(&self, app: &mut App) {
+        self.track_loading.apply(app);
+
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .init_resource::<SpecializedRenderPipelines<PostProcessPipeline>>()
+                .init_resource::<PostProcessUniforms>()
+                .add_systems(ExtractSchedule, extract_post_process)
+                .add_systems(Render, prepare_post_process.in_set(RenderSet::PrepareResources))
+                .add_render_graph_node::<ViewNodeRunner<PostProcessNode>>(Core2d, PostProcessPass)
+                .add_render_graph_edges(
+                    Core2d,
+                    (Node2d::Tonemapping, PostProcessPass, Node2d::EndMainPassPostProcessing),
+                );
+        }
+    }
+
+    fn finish(&self, app: &mut App) {
+        let shader = PostProcessShader {
+            handle: app.world.resource::<AssetServer>().load(PostProcessShader::SOURCE),
+        };
+        app.insert_resource(shader.clone());
+
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app.insert_resource(shader).init_resource::<PostProcessPipeline>();
+        }
+    }
+}
+
+/// Darkens the image towards its corners.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct Vignette {
+    /// How much of `color` the corners end up with.
+    pub intensity: f32,
+    /// Distance from the center where darkening starts, `1.0` reaching the middle of the edges.
+    pub radius: f32,
+    /// Distance over which it fades in past `radius`.
+    pub smoothness: f32,
+    pub color: Color,
+}
+
+impl Default for Vignette {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            intensity: 0.6,
+            radius: 0.6,
+            smoothness: 0.8,
+            color: Color::BLACK,
+        }
+    }
+}
+
+/// Splits red and blue apart radially, like a cheap lens does.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct ChromaticAberration {
+    /// Offset of the red and blue channels at the corners, as a fraction of the viewport.
+    pub intensity: f32,
+}
+
+impl Default for ChromaticAberration {
+    #[inline]
+    fn default() -> Self {
+        Self { intensity: 0.004 }
+    }
+}
+
+/// Animated luminance noise.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct FilmGrain {
+    pub intensity: f32,
+    /// Size of a grain in physical pixels.
+    pub size: f32,
+    /// Grain patterns shown per second.
+    pub speed: f32,
+}
+
+impl Default for FilmGrain {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            intensity: 0.06,
+            size: 1.5,
+            speed: 24.0,
+        }
+    }
+}
+
+/// Horizontal scanlines, optionally on a curved screen.
+#[derive(Component, Copy, Clone, Debug)]
+pub struct CrtScanlines {
+    /// Distance between scanlines in physical pixels.
+    pub period: f32,
+    /// How much scanlines darken.
+    pub strength: f32,
+    /// Barrel distortion, `0.0` for a flat screen.
+    pub curvature: f32,
+}
+
+impl Default for CrtScanlines {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            period: 3.0,
+            strength: 0.2,
+            curvature: 0.0,
+        }
+    }
+}
+
+/// Color grading through a lookup table, applied to tonemapped colors encoded as sRGB.
+///
+/// The table is a strip of `size` square slices of `size` texels each, laid out left to right: red grows along X within
+/// a slice, green along Y, and blue from slice to slice. Such a strip can be made in any image editor by grading an
+/// identity strip, or baked with [`ColorLut::bake`]. It should be loaded as linear rather than sRGB, and is skipped
+/// until it is.
+#[derive(Component, Clone, Debug)]
+pub struct ColorLut {
+    pub texture: Handle<Image>,
+    /// Blend between the original and graded colors.
+    pub intensity: f32,
+}
+
+impl ColorLut {
+    #[inline]
+    pub fn new(texture: Handle<Image>) -> Self {
+        Self { texture, intensity: 1.0 }
+    }
+
+    /// A `size`-texel strip mapping every sRGB color to `grade` of it.
+    pub fn bake(size: u32, grade: impl Fn(Vec3) -> Vec3) -> Image {
+        let max = (size - 1).max(1) as f32;
+        let mut data = Vec::with_capacity((size * size * size * 4) as usize);
+        for green in 0..size {
+            for blue in 0..size {
+                for red in 0..size {
+                    let color = grade(Vec3::new(red as f32, green as f32, blue as f32) / max);
+                    let [r, g, b] = color
+                        .clamp(Vec3::ZERO, Vec3::ONE)
+                        .to_array()
+                        .map(|c| (c * 255.0).round() as u8);
+                    data.extend([r, g, b, 255]);
+                }
+            }
+        }
+
+        Image::new(
+            Extent3d {
+                width: size * size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8Unorm,
+            RenderAssetUsages::RENDER_WORLD,
+        )
+    }
+}
+
+/// Post-process effects of a view, merged from its components.
+#[derive(Component)]
+pub struct ExtractedPostProcess {
+    pub effects: PostProcessEffects,
+    /// Time and the LUT's size are filled in by the renderer.
+    pub uniform: PostProcessUniform,
+    pub lut: Option<AssetId<Image>>,
+}
+
+type PostProcessCameras<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Camera,
+        Option<&'static Vignette>,
+        Option<&'static ChromaticAberration>,
+        Option<&'static FilmGrain>,
+        Option<&'static CrtScanlines>,
+        Option<&'static ColorLut>,
+    ),
+    With<Camera2d>,
+>;
+
+fn extract_post_process(mut commands: Commands, cameras: Extract<PostProcessCameras>) {
+    for (entity, camera, vignette, aberration, grain, scanlines, lut) in &cameras {
+        let effects = PostProcessEffects {
+            vignette: vignette.is_some(),
+            chromatic_aberration: aberration.is_some(),
+            film_grain: grain.is_some(),
+            crt_scanlines: scanlines.is_some(),
+            color_lut: lut.is_some(),
+        };
+
+        if !camera.is_active || effects == PostProcessEffects::default() {
+            continue
+        }
+
+        let vignette = vignette.copied().unwrap_or_default();
+        let grain = grain.copied().unwrap_or_default();
+        let scanlines = scanlines.copied().unwrap_or_default();
+        commands.get_or_spawn(entity).insert(ExtractedPostProcess {
+            effects,
+            uniform: PostProcessUniform {
+                vignette_color: Vec4::from_array(vignette.color.as_linear_rgba_f32()),
+                vignette: Vec4::new(vignette.intensity, vignette.radius, vignette.smoothness.max(1e-4), 0.0),
+                grain: Vec4::new(grain.intensity, grain.size.max(1.0), grain.speed, 0.0),
+                scanlines: Vec4::new(scanlines.period.max(1.0), scanlines.strength, scanlines.curvature, 0.0),
+                aberration: aberration.map_or(0.0, |aberration| aberration.intensity),
+                lut_intensity: lut.map_or(0.0, |lut| lut.intensity),
+                lut_size: 0.0,
+                time: 0.0,
+            },
+            lut: lut.map(|lut| lut.texture.id()),
+        });
+    }
+}
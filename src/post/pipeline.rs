@@ -0,0 +1,267 @@
+use bevy::{
+    core_pipeline::fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    ecs::query::QueryItem,
+    prelude::*,
+    render::{
+        render_asset::RenderAssets,
+        render_graph::{NodeRunError, RenderGraphContext, RenderLabel, ViewNode},
+        render_resource::{
+            binding_types::{sampler, texture_2d, uniform_buffer},
+            BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, CachedRenderPipelineId, ColorTargetState,
+            ColorWrites, DynamicUniformBuffer, FilterMode, FragmentState, MultisampleState, Operations, PipelineCache,
+            PrimitiveState, RenderPassColorAttachment, RenderPassDescriptor, RenderPipelineDescriptor, Sampler,
+            SamplerBindingType, SamplerDescriptor, ShaderStages, SpecializedRenderPipeline, SpecializedRenderPipelines,
+            TextureFormat, TextureSampleType,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        texture::{BevyDefault, FallbackImage},
+        view::{ExtractedView, ViewTarget},
+    },
+};
+
+use crate::post::ExtractedPostProcess;
+
+/// The strong handle to the post-process shader, in both the main and the render world.
+#[derive(Resource, Clone)]
+pub struct PostProcessShader {
+    pub handle: Handle<Shader>,
+}
+
+impl PostProcessShader {
+    pub const SOURCE: &'static str = "shaders/post.wgsl";
+}
+
+/// Effects a view has, each enabled through a shader def.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Default, Debug)]
+pub struct PostProcessEffects {
+    pub vignette: bool,
+    pub chromatic_aberration: bool,
+    pub film_grain: bool,
+    pub crt_scanlines: bool,
+    pub color_lut: bool,
+}
+
+pub use layout::PostProcessUniform;
+
+// Like the shape uniforms, the layout checks `ShaderType` emits are only reachable from an enclosing module.
+#[allow(dead_code)]
+mod layout {
+    use bevy::{prelude::*, render::render_resource::ShaderType};
+
+    /// Per-view uniform block of the post-process pass.
+    #[derive(Copy, Clone, ShaderType)]
+    pub struct PostProcessUniform {
+        pub vignette_color: Vec4,
+        /// Intensity, radius, and smoothness.
+        pub vignette: Vec4,
+        /// Intensity, size, and speed.
+        pub grain: Vec4,
+        /// Period, strength, and curvature.
+        pub scanlines: Vec4,
+        pub aberration: f32,
+        pub lut_intensity: f32,
+        /// Slices of the LUT; filled in by the renderer.
+        pub lut_size: f32,
+        /// Wrapped elapsed seconds; filled in by the renderer.
+        pub time: f32,
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct PostProcessUniforms(pub DynamicUniformBuffer<PostProcessUniform>);
+
+/// Pipeline and uniform offset of a view's post-process pass.
+#[derive(Component)]
+pub struct PostProcessView {
+    pub pipeline: CachedRenderPipelineId,
+    pub offset: u32,
+}
+
+#[derive(Resource)]
+pub struct PostProcessPipeline {
+    shader: Handle<Shader>,
+    layout: BindGroupLayout,
+    source_sampler: Sampler,
+    lut_sampler: Sampler,
+}
+
+impl FromWorld for PostProcessPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let shader = world.resource::<PostProcessShader>().handle.clone();
+        let device = world.resource::<RenderDevice>();
+
+        Self {
+            shader,
+            layout: device.create_bind_group_layout(
+                "post_process_layout",
+                &BindGroupLayoutEntries::sequential(
+                    ShaderStages::FRAGMENT,
+                    (
+                        texture_2d(TextureSampleType::Float { filterable: true }),
+                        sampler(SamplerBindingType::Filtering),
+                        uniform_buffer::<PostProcessUniform>(true),
+                        texture_2d(TextureSampleType::Float { filterable: true }),
+                        sampler(SamplerBindingType::Filtering),
+                    ),
+                ),
+            ),
+            source_sampler: device.create_sampler(&SamplerDescriptor::default()),
+            lut_sampler: device.create_sampler(&SamplerDescriptor {
+                mag_filter: FilterMode::Linear,
+                min_filter: FilterMode::Linear,
+                ..default()
+            }),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+pub struct PostProcessKey {
+    pub hdr: bool,
+    pub effects: PostProcessEffects,
+}
+
+impl SpecializedRenderPipeline for PostProcessPipeline {
+    type Key = PostProcessKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let PostProcessEffects {
+            vignette,
+            chromatic_aberration,
+            film_grain,
+            crt_scanlines,
+            color_lut,
+        } = key.effects;
+
+        let shader_defs = [
+            (vignette, "VIGNETTE"),
+            (chromatic_aberration, "CHROMATIC_ABERRATION"),
+            (film_grain, "FILM_GRAIN"),
+            (crt_scanlines, "CRT_SCANLINES"),
+            (color_lut, "COLOR_LUT"),
+        ]
+        .into_iter()
+        .filter(|&(enabled, _)| enabled)
+        .map(|(_, def)| def.into())
+        .collect();
+
+        RenderPipelineDescriptor {
+            label: Some("post_process_pipeline".into()),
+            layout: vec![self.layout.clone()],
+            push_constant_ranges: Vec::new(),
+            vertex: fullscreen_shader_vertex_state(),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                shader: self.shader.clone(),
+                shader_defs,
+                entry_point: "fragment_main".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: match key.hdr {
+                        true => ViewTarget::TEXTURE_FORMAT_HDR,
+                        false => TextureFormat::bevy_default(),
+                    },
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn prepare_post_process(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    time: Res<Time>,
+    images: Res<RenderAssets<Image>>,
+    pipeline: Res<PostProcessPipeline>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<PostProcessPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    mut uniforms: ResMut<PostProcessUniforms>,
+    views: Query<(Entity, &ExtractedView, &ExtractedPostProcess)>,
+) {
+    let time = time.elapsed_seconds_wrapped();
+    let Some(mut writer) = uniforms.0.get_writer(views.iter().len(), &device, &queue) else {
+        return
+    };
+
+    for (entity, view, post) in &views {
+        let mut effects = post.effects;
+        let mut uniform = PostProcessUniform { time, ..post.uniform };
+
+        // Grading with the fallback image would wash everything out, so the LUT waits until it's loaded.
+        match post.lut.and_then(|lut| images.get(lut)) {
+            Some(lut) => uniform.lut_size = lut.size.y,
+            None => effects.color_lut = false,
+        }
+
+        let pipeline = pipelines.specialize(&pipeline_cache, &pipeline, PostProcessKey { hdr: view.hdr, effects });
+        commands.entity(entity).insert(PostProcessView {
+            pipeline,
+            offset: writer.write(&uniform),
+        });
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+pub struct PostProcessPass;
+
+#[derive(Default)]
+pub struct PostProcessNode;
+impl ViewNode for PostProcessNode {
+    type ViewQuery = (&'static ViewTarget, &'static ExtractedPostProcess, &'static PostProcessView);
+
+    fn run<'w>(
+        &self,
+        _: &mut RenderGraphContext,
+        render_context: &mut RenderContext<'w>,
+        (target, post, view): QueryItem<'w, Self::ViewQuery>,
+        world: &'w World,
+    ) -> Result<(), NodeRunError> {
+        let post_pipeline = world.resource::<PostProcessPipeline>();
+        let (Some(pipeline), Some(uniforms)) = (
+            world.resource::<PipelineCache>().get_render_pipeline(view.pipeline),
+            world.resource::<PostProcessUniforms>().0.binding(),
+        ) else {
+            return Ok(())
+        };
+
+        let lut = match post.lut.and_then(|lut| world.resource::<RenderAssets<Image>>().get(lut)) {
+            Some(lut) => &lut.texture_view,
+            None => &world.resource::<FallbackImage>().d2.texture_view,
+        };
+
+        let source = target.post_process_write();
+        let bind_group = render_context.render_device().create_bind_group(
+            "post_process_bind_group",
+            &post_pipeline.layout,
+            &BindGroupEntries::sequential((
+                source.source,
+                &post_pipeline.source_sampler,
+                uniforms,
+                lut,
+                &post_pipeline.lut_sampler,
+            )),
+        );
+
+        let mut pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("post_process_pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: source.destination,
+                resolve_target: None,
+                ops: Operations::default(),
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        pass.set_render_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[view.offset]);
+        pass.draw(0..3, 0..1);
+        Ok(())
+    }
+}
@@ -0,0 +1,260 @@
+use std::{mem, ops::Range};
+
+use bevy::{
+    core::Pod,
+    prelude::*,
+    render::{
+        extract_resource::ExtractResource,
+        render_resource::{Buffer, BufferAddress, BufferDescriptor, BufferUsages},
+        renderer::{RenderDevice, RenderQueue},
+    },
+};
+
+/// When GPU buffers of the shape renderer, and the CPU-side copies of their contents, give back memory.
+#[derive(Resource, ExtractResource, Copy, Clone, Debug)]
+pub struct ShrinkPolicy {
+    /// Frames over which the peak length of a buffer is measured.
+    pub frames: u32,
+    /// A buffer shrinks to fit its peak length once its capacity is at least this many times as large.
+    pub factor: usize,
+}
+
+impl Default for ShrinkPolicy {
+    #[inline]
+    fn default() -> Self {
+        Self { frames: 300, factor: 4 }
+    }
+}
+
+/// Capacity of a GPU buffer, growing to the next power of two and shrinking per [`ShrinkPolicy`].
+#[derive(Default)]
+pub struct Capacity {
+    capacity: usize,
+    peak: usize,
+    frames: u32,
+}
+
+impl Capacity {
+    #[inline]
+    pub fn get(&self) -> usize {
+        self.capacity
+    }
+
+    /// Accounts for a frame using `len` values. Returns whether the buffer has to be reallocated.
+    pub fn reserve(&mut self, len: usize, policy: &ShrinkPolicy) -> bool {
+        self.peak = self.peak.max(len);
+        self.frames += 1;
+
+        if len > self.capacity {
+            self.capacity = len.next_power_of_two();
+            self.peak = len;
+            self.frames = 0;
+            return true
+        }
+
+        if self.frames >= policy.frames {
+            let peak = mem::replace(&mut self.peak, len);
+            self.frames = 0;
+
+            let fit = peak.next_power_of_two();
+            if peak.saturating_mul(policy.factor) <= self.capacity && fit < self.capacity {
+                self.capacity = fit;
+                return true
+            }
+        }
+
+        false
+    }
+}
+
+/// A GPU buffer refilled every frame, keeping its allocation across frames. Only blocks whose contents differ from
+/// the last upload are re-sent, so mostly static scenes upload little even though they are redrawn.
+///
+/// Like `BufferVec`, writes have to be a multiple of 4 bytes long.
+pub struct FrameBuffer<T: Pod> {
+    label: &'static str,
+    usage: BufferUsages,
+    values: Vec<T>,
+    uploaded: Vec<T>,
+    buffer: Option<Buffer>,
+    capacity: Capacity,
+}
+
+impl<T: Pod> FrameBuffer<T> {
+    /// Values compared at once; a multiple of 2 so blocks of `u16`s are 4-byte aligned.
+    const BLOCK: usize = 1024;
+
+    #[inline]
+    pub fn new(label: &'static str, usage: BufferUsages) -> Self {
+        Self {
+            label,
+            usage,
+            values: Vec::new(),
+            uploaded: Vec::new(),
+            buffer: None,
+            capacity: default(),
+        }
+    }
+
+    #[inline]
+    pub fn buffer(&self) -> Option<&Buffer> {
+        self.buffer.as_ref()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    #[inline]
+    pub fn values_mut(&mut self) -> &mut Vec<T> {
+        &mut self.values
+    }
+
+    #[inline]
+    pub fn push(&mut self, value: T) -> usize {
+        self.values.push(value);
+        self.values.len() - 1
+    }
+
+    /// Clears the values, keeping their allocation.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+
+    /// Returns the number of bytes uploaded.
+    pub fn write_buffer(&mut self, device: &RenderDevice, queue: &RenderQueue, policy: &ShrinkPolicy) -> u64 {
+        if self.capacity.reserve(self.values.len(), policy) {
+            self.buffer = None;
+            self.uploaded.clear();
+
+            let capacity = self.capacity.get();
+            self.values.shrink_to(capacity);
+            self.uploaded.shrink_to(capacity);
+        }
+
+        if self.values.is_empty() || size_of::<T>() == 0 {
+            return 0
+        }
+
+        let buffer = self.buffer.get_or_insert_with(|| {
+            device.create_buffer(&BufferDescriptor {
+                label: Some(self.label),
+                size: (self.capacity.get() * size_of::<T>()) as BufferAddress,
+                usage: BufferUsages::COPY_DST | self.usage,
+                mapped_at_creation: false,
+            })
+        });
+
+        let mut bytes = 0;
+        for Range { start, end } in Self::changed(&self.values, &self.uploaded) {
+            queue.write_buffer(
+                buffer,
+                (start * size_of::<T>()) as BufferAddress,
+                bytemuck::cast_slice(&self.values[start..end]),
+            );
+            bytes += ((end - start) * size_of::<T>()) as u64;
+        }
+
+        self.uploaded.clear();
+        self.uploaded.extend_from_slice(&self.values);
+        bytes
+    }
+
+    /// Ranges of the blocks of `values` that differ from `uploaded`, consecutive ones coalesced into single ranges.
+    fn changed(values: &[T], uploaded: &[T]) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        let mut changed = None::<usize>;
+        for start in (0..values.len()).step_by(Self::BLOCK) {
+            let end = (start + Self::BLOCK).min(values.len());
+            let same = uploaded.get(start..end).is_some_and(|uploaded| {
+                bytemuck::cast_slice::<T, u8>(uploaded) == bytemuck::cast_slice::<T, u8>(&values[start..end])
+            });
+
+            match (same, changed) {
+                (false, None) => changed = Some(start),
+                (true, Some(from)) => {
+                    ranges.push(from..start);
+                    changed = None;
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(from) = changed {
+            ranges.push(from..values.len());
+        }
+
+        ranges
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use crate::shape::buffer::{Capacity, FrameBuffer, ShrinkPolicy};
+
+    #[test]
+    fn only_changed_blocks_are_uploaded() {
+        let changed = FrameBuffer::<u32>::changed;
+        let uploaded = (0..3000).collect::<Vec<u32>>();
+        let mut values = uploaded.clone();
+        assert!(changed(&values, &uploaded).is_empty());
+
+        values[1500] = 0;
+        assert_eq!(changed(&values, &uploaded), [1024..2048]);
+
+        values[100] = 0;
+        values[2500] = 0;
+        assert_eq!(changed(&values, &uploaded), [0..3000]);
+
+        values[1500] = 1500;
+        assert_eq!(changed(&values, &uploaded), [0..1024, 2048..3000]);
+    }
+
+    #[test]
+    fn blocks_past_the_upload_are_changed() {
+        let changed = FrameBuffer::<u32>::changed;
+        let values = (0..3000).collect::<Vec<u32>>();
+        assert_eq!(changed(&values, &[]), [0..3000]);
+        assert_eq!(changed(&values, &values[..2000]), [1024..3000]);
+        assert_eq!(changed(&values, &values[..2048]), [2048..3000]);
+    }
+
+    #[test]
+    fn capacity_shrinks_after_idle_frames() {
+        let policy = ShrinkPolicy { frames: 3, factor: 4 };
+        let mut capacity = Capacity::default();
+        assert!(capacity.reserve(1000, &policy));
+        assert_eq!(capacity.get(), 1024);
+
+        // The peak of the first window still includes the growth.
+        for _ in 0..3 {
+            assert!(!capacity.reserve(10, &policy));
+        }
+
+        assert!(!capacity.reserve(10, &policy));
+        assert!(!capacity.reserve(10, &policy));
+        assert!(capacity.reserve(10, &policy));
+        assert_eq!(capacity.get(), 16);
+    }
+
+    #[test]
+    fn capacity_keeps_busy_buffers() {
+        let policy = ShrinkPolicy { frames: 2, factor: 4 };
+        let mut capacity = Capacity::default();
+        assert!(capacity.reserve(1000, &policy));
+
+        for len in [10, 300, 10, 300, 10, 300] {
+            assert!(!capacity.reserve(len, &policy));
+        }
+
+        assert_eq!(capacity.get(), 1024);
+    }
+}
@@ -0,0 +1,21 @@
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+
+/// Debug views of the shape renderer, toggleable at runtime.
+#[derive(Resource, ExtractResource, Copy, Clone, Default, Debug)]
+pub struct ShapeDebug {
+    /// Draws triangle edges only. Needs `POLYGON_MODE_LINE`, and is ignored with a warning where it isn't supported.
+    pub wireframe: bool,
+    /// Tints every batch with a distinct color, making batch breaks visible.
+    pub batch_tint: bool,
+    /// Draws every fragment as a constant additive color, so brighter areas are drawn more often. Ignores materials.
+    pub overdraw: bool,
+}
+
+impl ShapeDebug {
+    /// Distinct tint of the `index`-th batch of a frame.
+    #[inline]
+    pub fn batch_color(index: u32) -> Vec4 {
+        // Spreads consecutive hues by the golden angle.
+        Vec4::from_array(Color::hsl((index as f32 * 137.507_77) % 360.0, 0.8, 0.6).as_linear_rgba_f32())
+    }
+}
@@ -0,0 +1,343 @@
+use std::{
+    error::Error,
+    fmt,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+};
+
+use bevy::{
+    asset::LoadState,
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
+    prelude::*,
+    render::{render_phase::RenderCommandResult, Render, RenderApp, RenderSet},
+    utils::get_short_name,
+};
+
+use crate::shape::{pipeline::ShapeShader, uniform::MaterialUniform, vertex::Vertex};
+
+/// Shares [`ShapeErrors`] between the main and render world, snapshots them at the end of every render frame, and
+/// reports the snapshots as diagnostics; added by the first [`ShapePlugin`].
+///
+/// [`ShapePlugin`]: crate::shape::ShapePlugin
+pub struct ShapeDiagnosticsPlugin;
+impl Plugin for ShapeDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        let errors = ShapeErrors::default();
+        for kind in ShapeError::KINDS {
+            app.register_diagnostic(Diagnostic::new(kind.clone()).with_suffix(" errors"));
+        }
+
+        app.insert_resource(errors.clone()).add_systems(Last, report_errors);
+
+        match app.get_sub_app_mut(RenderApp) {
+            Ok(render_app) => {
+                render_app
+                    .insert_resource(errors)
+                    .add_systems(Render, snapshot_errors.in_set(RenderSet::Cleanup));
+            }
+            // Without a render world, errors are only ever reported from the main world.
+            Err(..) => {
+                app.add_systems(Last, snapshot_errors.before(report_errors));
+            }
+        }
+    }
+}
+
+/// Failures the shape renderer recovers from instead of panicking, or skipping frames without a word.
+#[derive(Debug, Copy, Clone)]
+pub enum ShapeError {
+    /// The shader of `vertex` failed to load, so none of its pipelines compile.
+    MissingShader { vertex: &'static str },
+    /// `lock` was poisoned by a panicking thread; the requests it holds are used regardless.
+    PoisonedLock { lock: &'static str },
+    /// `buffer` wasn't written to the GPU even though a batch draws from it.
+    EmptyBuffer { buffer: &'static str },
+    /// `group` wasn't prepared even though a batch binds it.
+    MissingBindGroup { group: &'static str },
+    /// A queued batch lost its [`BatchSection`](crate::shape::pipeline::BatchSection).
+    MissingSection,
+    /// A [`ShapeMaterial`](crate::shape::material::ShapeMaterial) has `params` parameters, more than its uniform
+    /// holds; the rest are dropped.
+    TooManyParams { params: usize },
+    /// Shapes were drawn through [`Shapes::sdf`](crate::shape::vertex::Shapes::sdf) without the
+    /// [`SdfPlugin`](crate::shape::sdf::SdfPlugin) to render them.
+    MissingSdfPlugin,
+}
+
+impl ShapeError {
+    /// Diagnostic paths counting every kind of error per frame, indexed by [`kind`](Self::kind).
+    pub const KINDS: [DiagnosticPath; 7] = [
+        DiagnosticPath::const_new("shape/errors/missing_shader"),
+        DiagnosticPath::const_new("shape/errors/poisoned_lock"),
+        DiagnosticPath::const_new("shape/errors/empty_buffer"),
+        DiagnosticPath::const_new("shape/errors/missing_bind_group"),
+        DiagnosticPath::const_new("shape/errors/missing_section"),
+        DiagnosticPath::const_new("shape/errors/too_many_params"),
+        DiagnosticPath::const_new("shape/errors/missing_sdf_plugin"),
+    ];
+
+    #[inline]
+    pub const fn kind(self) -> usize {
+        match self {
+            Self::MissingShader { .. } => 0,
+            Self::PoisonedLock { .. } => 1,
+            Self::EmptyBuffer { .. } => 2,
+            Self::MissingBindGroup { .. } => 3,
+            Self::MissingSection => 4,
+            Self::TooManyParams { .. } => 5,
+            Self::MissingSdfPlugin => 6,
+        }
+    }
+}
+
+impl fmt::Display for ShapeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingShader { vertex } => write!(f, "shader of `{vertex}` failed to load"),
+            Self::PoisonedLock { lock } => write!(f, "`{lock}` was poisoned by a panicking thread"),
+            Self::EmptyBuffer { buffer } => write!(f, "`{buffer}` is drawn from but wasn't written"),
+            Self::MissingBindGroup { group } => write!(f, "`{group}` is bound but wasn't prepared"),
+            Self::MissingSection => write!(f, "a queued batch has no section to draw"),
+            Self::TooManyParams { params } => write!(
+                f,
+                "a material has {params} params, but only the first {} are bound",
+                MaterialUniform::MAX_PARAMS
+            ),
+            Self::MissingSdfPlugin => write!(f, "SDF shapes were drawn without `SdfPlugin`"),
+        }
+    }
+}
+
+impl Error for ShapeError {}
+
+#[derive(Default)]
+struct ErrorCounter {
+    frame: AtomicU32,
+    published: AtomicU32,
+    failing: AtomicBool,
+}
+
+/// Per-frame error counts, shared between the main and render world.
+///
+/// Like [`ShapeStats`], they're counted while the main world runs its next frame, so the main world only reads what
+/// the last complete render frame counted.
+#[derive(Resource, Clone, Default)]
+pub struct ShapeErrors(Arc<[ErrorCounter; 7]>);
+
+impl ShapeErrors {
+    /// Counts `error` towards this frame. Only logs if the same kind of error didn't already occur last frame, so
+    /// persistent failures don't flood the log.
+    pub fn report(&self, error: ShapeError) {
+        let counter = &self.0[error.kind()];
+        if counter.frame.fetch_add(1, Ordering::Relaxed) == 0 && !counter.failing.load(Ordering::Relaxed) {
+            warn!("{error}");
+        }
+    }
+
+    /// Reports `error` from a render command that can't draw its item.
+    #[inline]
+    pub fn fail(&self, error: ShapeError) -> RenderCommandResult {
+        self.report(error);
+        RenderCommandResult::Failure
+    }
+
+    /// Locks `mutex`, recovering its contents if it was poisoned.
+    pub fn lock<'a, T>(&self, mutex: &'a Mutex<T>, lock: &'static str) -> MutexGuard<'a, T> {
+        mutex.lock().unwrap_or_else(|e| {
+            self.report(ShapeError::PoisonedLock { lock });
+            mutex.clear_poison();
+            e.into_inner()
+        })
+    }
+
+    /// Like [`lock`](Self::lock), for exclusive access.
+    pub fn get_mut<'a, T>(&self, mutex: &'a mut Mutex<T>, lock: &'static str) -> &'a mut T {
+        if mutex.is_poisoned() {
+            self.report(ShapeError::PoisonedLock { lock });
+            mutex.clear_poison();
+        }
+
+        mutex.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub fn check_shader<T: Vertex>(server: Res<AssetServer>, shader: Res<ShapeShader<T>>, errors: Res<ShapeErrors>) {
+    if server.get_load_state(&shader.handle) == Some(LoadState::Failed) {
+        errors.report(ShapeError::MissingShader {
+            vertex: std::any::type_name::<T>(),
+        });
+    }
+}
+
+fn snapshot_errors(errors: Res<ShapeErrors>) {
+    for counter in &*errors.0 {
+        let count = counter.frame.swap(0, Ordering::Relaxed);
+        counter.failing.store(count > 0, Ordering::Relaxed);
+        counter.published.store(count, Ordering::Relaxed);
+    }
+}
+
+fn report_errors(mut diagnostics: Diagnostics, errors: Res<ShapeErrors>) {
+    for (path, counter) in ShapeError::KINDS.iter().zip(&*errors.0) {
+        let count = counter.published.load(Ordering::Relaxed);
+        diagnostics.add_measurement(path, || count as f64);
+    }
+}
+
+/// Per-frame statistics of a vertex type, published as `shape/<vertex type>/<stat>` diagnostics.
+#[derive(Debug, Copy, Clone)]
+pub enum ShapeStat {
+    /// Requests queued, whether drawn this frame or retained.
+    Requests,
+    /// Vertices of requests drawn this frame; retained geometry only counts towards `RetainedVertices`.
+    Vertices,
+    /// Indices drawn, including retained ones.
+    Indices,
+    /// Instances drawn, including retained ones.
+    Instances,
+    /// Vertices resident in the retained buffer.
+    RetainedVertices,
+    /// Batches queued, once regardless of how many views draw them.
+    Batches,
+    /// Pipelines specialized for the first time.
+    Specializations,
+    /// Bytes written to GPU buffers.
+    BytesUploaded,
+}
+
+impl ShapeStat {
+    pub const ALL: [Self; 8] = [
+        Self::Requests,
+        Self::Vertices,
+        Self::Indices,
+        Self::Instances,
+        Self::RetainedVertices,
+        Self::Batches,
+        Self::Specializations,
+        Self::BytesUploaded,
+    ];
+
+    #[inline]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Requests => "requests",
+            Self::Vertices => "vertices",
+            Self::Indices => "indices",
+            Self::Instances => "instances",
+            Self::RetainedVertices => "retained_vertices",
+            Self::Batches => "batches",
+            Self::Specializations => "specializations",
+            Self::BytesUploaded => "bytes_uploaded",
+        }
+    }
+
+    /// Whether this stat is [`set`](ShapeStats::set) to a level rather than counted per frame, so it isn't reset.
+    #[inline]
+    pub const fn is_gauge(self) -> bool {
+        matches!(self, Self::RetainedVertices)
+    }
+
+    #[inline]
+    pub fn path<T: Vertex>(self) -> DiagnosticPath {
+        DiagnosticPath::from_components(["shape", &get_short_name(std::any::type_name::<T>()), self.name()])
+    }
+}
+
+/// Shares [`ShapeStats`] of `T` between the main and render world, snapshots them at the end of every render frame, and
+/// publishes the snapshots as diagnostics; added by [`ShapePlugin`].
+///
+/// [`ShapePlugin`]: crate::shape::ShapePlugin
+pub struct ShapeStatsPlugin<T: Vertex> {
+    _marker: PhantomData<fn(T)>,
+}
+
+impl<T: Vertex> Default for ShapeStatsPlugin<T> {
+    #[inline]
+    fn default() -> Self {
+        Self { _marker: PhantomData }
+    }
+}
+
+impl<T: Vertex> Plugin for ShapeStatsPlugin<T> {
+    fn build(&self, app: &mut App) {
+        let stats = ShapeStats::<T>::default();
+        for path in stats.paths.iter() {
+            app.register_diagnostic(Diagnostic::new(path.clone()));
+        }
+
+        app.insert_resource(stats.clone()).add_systems(Last, publish_stats::<T>);
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .insert_resource(stats)
+                .add_systems(Render, snapshot_stats::<T>.in_set(RenderSet::Cleanup));
+        }
+    }
+}
+
+/// Per-frame counts of every [`ShapeStat`] of `T`, shared between the main and render world.
+///
+/// The render world counts into them while the main world runs its next frame, so the main world only reads what the
+/// last complete render frame counted.
+#[derive(Resource)]
+pub struct ShapeStats<T: Vertex> {
+    counters: Arc<[AtomicU64; 8]>,
+    published: Arc<[AtomicU64; 8]>,
+    paths: Arc<[DiagnosticPath; 8]>,
+    _marker: PhantomData<fn(T)>,
+}
+
+impl<T: Vertex> ShapeStats<T> {
+    #[inline]
+    pub fn add(&self, stat: ShapeStat, count: u64) {
+        self.counters[stat as usize].fetch_add(count, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn set(&self, stat: ShapeStat, count: u64) {
+        self.counters[stat as usize].store(count, Ordering::Relaxed);
+    }
+}
+
+impl<T: Vertex> Default for ShapeStats<T> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            counters: default(),
+            published: default(),
+            paths: Arc::new(ShapeStat::ALL.map(ShapeStat::path::<T>)),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Vertex> Clone for ShapeStats<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            counters: self.counters.clone(),
+            published: self.published.clone(),
+            paths: self.paths.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+fn snapshot_stats<T: Vertex>(stats: Res<ShapeStats<T>>) {
+    for ((stat, counter), published) in ShapeStat::ALL.into_iter().zip(&*stats.counters).zip(&*stats.published) {
+        let count = match stat.is_gauge() {
+            true => counter.load(Ordering::Relaxed),
+            false => counter.swap(0, Ordering::Relaxed),
+        };
+        published.store(count, Ordering::Relaxed);
+    }
+}
+
+fn publish_stats<T: Vertex>(mut diagnostics: Diagnostics, stats: Res<ShapeStats<T>>) {
+    for (path, published) in stats.paths.iter().zip(&*stats.published) {
+        let count = published.load(Ordering::Relaxed);
+        diagnostics.add_measurement(path, || count as f64);
+    }
+}
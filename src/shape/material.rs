@@ -0,0 +1,129 @@
+use bevy::{
+    ecs::system::{lifetimeless::SRes, SystemParamItem},
+    prelude::*,
+    render::{
+        render_asset::{PrepareAssetError, RenderAsset, RenderAssetPlugin, RenderAssetUsages},
+        render_resource::{
+            binding_types::uniform_buffer, BindGroup, BindGroupEntries, BindGroupLayout, ShaderStages, UniformBuffer,
+        },
+        renderer::{RenderDevice, RenderQueue},
+    },
+};
+
+use crate::shape::{
+    diagnostic::{ShapeError, ShapeErrors},
+    uniform::MaterialUniform,
+};
+
+/// Registers [`ShapeMaterial`] as an asset; added by the first [`ShapePlugin`].
+///
+/// [`ShapePlugin`]: crate::shape::ShapePlugin
+pub struct ShapeMaterialPlugin;
+impl Plugin for ShapeMaterialPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<ShapeMaterial>()
+            .add_plugins(RenderAssetPlugin::<ShapeMaterial>::default());
+    }
+}
+
+/// A fragment shader and its parameters, created at runtime and referenced from vertex keys through
+/// [`VertexKey::material`].
+///
+/// The shader replaces the fragment stage of the vertex type's own shader, so its `fragment_main` must take the same
+/// inputs the vertex stage outputs; for [`DrawVertex`] that is `@builtin(position)`, `@location(0) color`, and
+/// `@location(1) emissive`, which should multiply the color's RGB under the `HDR` shader def. It receives the same
+/// shader defs, and may bind the view at group 0, the batch uniform at group 1, and its parameters at group 2 binding 0
+/// as `struct { params: array<vec4<f32>, 16> }`.
+///
+/// [`VertexKey::material`]: crate::shape::vertex::VertexKey::material
+/// [`DrawVertex`]: crate::draw::vertex::DrawVertex
+#[derive(Asset, TypePath, Clone)]
+pub struct ShapeMaterial {
+    #[dependency]
+    pub shader: Handle<Shader>,
+    /// At most [`MaterialUniform::MAX_PARAMS`]; the rest are zeroed, and any past it are dropped with a
+    /// [`ShapeError::TooManyParams`].
+    pub params: Vec<Vec4>,
+}
+
+impl ShapeMaterial {
+    #[inline]
+    pub fn new(shader: Handle<Shader>) -> Self {
+        Self {
+            shader,
+            params: Vec::new(),
+        }
+    }
+
+    #[inline]
+    pub fn with_params(mut self, params: impl IntoIterator<Item = Vec4>) -> Self {
+        self.params = params.into_iter().collect();
+        self
+    }
+}
+
+pub struct PreparedShapeMaterial {
+    pub shader: AssetId<Shader>,
+    pub bind_group: BindGroup,
+}
+
+impl RenderAsset for ShapeMaterial {
+    type PreparedAsset = PreparedShapeMaterial;
+    type Param = (
+        SRes<RenderDevice>,
+        SRes<RenderQueue>,
+        SRes<ShapeMaterialLayout>,
+        SRes<ShapeErrors>,
+    );
+
+    #[inline]
+    fn asset_usage(&self) -> RenderAssetUsages {
+        RenderAssetUsages::default()
+    }
+
+    fn prepare_asset(
+        self,
+        (device, queue, layout, errors): &mut SystemParamItem<Self::Param>,
+    ) -> Result<Self::PreparedAsset, PrepareAssetError<Self>> {
+        if self.params.len() > MaterialUniform::MAX_PARAMS {
+            errors.report(ShapeError::TooManyParams {
+                params: self.params.len(),
+            });
+        }
+
+        let mut uniform = MaterialUniform {
+            params: [Vec4::ZERO; MaterialUniform::MAX_PARAMS],
+        };
+
+        for (dst, &src) in uniform.params.iter_mut().zip(&self.params) {
+            *dst = src;
+        }
+
+        let mut buffer = UniformBuffer::from(uniform);
+        buffer.write_buffer(device, queue);
+
+        let Some(binding) = buffer.binding() else {
+            return Err(PrepareAssetError::RetryNextUpdate(self))
+        };
+
+        Ok(PreparedShapeMaterial {
+            shader: self.shader.id(),
+            bind_group: device.create_bind_group("shape_material_group", &layout.0, &BindGroupEntries::single(binding)),
+        })
+    }
+}
+
+/// Layout of every [`ShapeMaterial`]'s bind group.
+#[derive(Resource)]
+pub struct ShapeMaterialLayout(pub BindGroupLayout);
+impl FromWorld for ShapeMaterialLayout {
+    fn from_world(world: &mut World) -> Self {
+        Self(
+            world
+                .resource::<RenderDevice>()
+                .create_bind_group_layout("shape_material_layout", &[
+                    uniform_buffer::<MaterialUniform>(false).build(0, ShaderStages::FRAGMENT)
+                ]),
+        )
+    }
+}
@@ -3,79 +3,158 @@ use std::marker::PhantomData;
 use bevy::{
     core_pipeline::core_2d::Transparent2d,
     prelude::*,
-    render::{render_phase::AddRenderCommand, render_resource::SpecializedRenderPipelines, Render, RenderApp, RenderSet},
+    render::{
+        extract_resource::ExtractResourcePlugin, render_phase::AddRenderCommand,
+        render_resource::SpecializedRenderPipelines, Render, RenderApp, RenderSet,
+    },
 };
-use iyes_progress::prelude::*;
 
 use crate::{
     shape::{
+        buffer::ShrinkPolicy,
+        debug::ShapeDebug,
+        diagnostic::{check_shader, ShapeDiagnosticsPlugin, ShapeStatsPlugin},
+        material::ShapeMaterialPlugin,
+        opaque::{ShapeOpaque2d, ShapeOpaquePlugin},
+        order::{order_requests, OrderRank, ShapeOrder},
         pipeline::{
-            prepare_vertices_batch, prepare_vertices_bind_group, queue_vertices, Batch, DrawShapes, Requests, ShapePipeline,
+            prepare_vertices_batch, prepare_vertices_bind_group, queue_vertices, sort_requests, Batch, BatchLimits,
+            BatchReorder, DrawShapes, Requests, ShapePipeline, ShapeShader,
         },
+        primitive::PrimitiveMeshes,
+        retain::RetainedBatch,
+        trail::{AfterimageShader, ShapeTrail2d, ShapeTrailPlugin},
         vertex::{DrawLayer, Vertex},
     },
-    GameState,
+    util::TrackLoading,
 };
 
+pub mod buffer;
+pub mod debug;
+pub mod diagnostic;
+pub mod material;
+pub mod opaque;
+pub mod order;
 pub mod pipeline;
+pub mod primitive;
+pub mod retain;
+pub mod sdf;
+pub mod trail;
+pub mod uniform;
 pub mod vertex;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, SystemSet)]
 pub enum ShapeSystems {
     ExtractShaper,
     QueueShaper,
+    QueueSdf,
+    SortVertices,
+    OrderVertices,
     QueueVertices,
     PrepareBatch,
     PrepareBindGroup,
 }
 
+/// Where a [`ShapePlugin`] gets its shader from.
+#[derive(Clone)]
+pub enum ShapeShaderSource {
+    /// Loaded through the [`AssetServer`], defaulting to [`Vertex::SHADER_SOURCE`].
+    Path(String),
+    /// Already loaded or embedded by the app, e.g. with `load_internal_asset!`.
+    Handle(Handle<Shader>),
+}
+
 pub struct ShapePlugin<T: Vertex> {
+    shader: ShapeShaderSource,
+    track_loading: TrackLoading,
     _marker: PhantomData<fn(T)>,
 }
 
+impl<T: Vertex> ShapePlugin<T> {
+    #[inline]
+    pub fn shader(mut self, shader: ShapeShaderSource) -> Self {
+        self.shader = shader;
+        self
+    }
+
+    /// Adds the shader, and the [`Afterimage`](trail::Afterimage) shader it shares with other shape plugins, to
+    /// [`AssetsLoading`](iyes_progress::prelude::AssetsLoading) when entering `state`, so an [`iyes_progress`] loading
+    /// state waits for them.
+    #[inline]
+    pub fn track_loading(mut self, state: impl States) -> Self {
+        self.track_loading = TrackLoading::default()
+            .shader(state.clone(), |shader: &ShapeShader<T>| &shader.handle)
+            .shader(state, |shader: &AfterimageShader| &shader.handle);
+        self
+    }
+}
+
 impl<T: Vertex> Default for ShapePlugin<T> {
     #[inline]
     fn default() -> Self {
-        Self { _marker: PhantomData }
+        Self {
+            shader: ShapeShaderSource::Path(T::SHADER_SOURCE.into()),
+            track_loading: default(),
+            _marker: PhantomData,
+        }
     }
 }
 
 impl<T: Vertex> Plugin for ShapePlugin<T> {
     fn build(&self, app: &mut App) {
-        #[derive(Resource)]
-        struct ShaderHandle(Handle<Shader>);
-
-        app.add_systems(
-            OnEnter(GameState::InitInternal),
-            move |mut commands: Commands, server: Res<AssetServer>, mut loading: ResMut<AssetsLoading>| {
-                let handle = server.load::<Shader>(T::SHADER_SOURCE);
-                loading.add(&handle);
-
-                commands.insert_resource(ShaderHandle(handle));
-            },
-        )
-        .add_systems(
-            OnExit(GameState::InitInternal),
-            move |mut commands: Commands, handle: Res<ShaderHandle>, mut shaders: ResMut<Assets<Shader>>| {
-                commands.remove_resource::<ShaderHandle>();
-
-                let shader = shaders.remove(&handle.0).unwrap();
-                shaders.insert(T::SHADER, shader);
-            },
-        );
+        if !app.is_plugin_added::<ShapeMaterialPlugin>() {
+            app.init_resource::<ShapeDebug>()
+                .init_resource::<BatchLimits>()
+                .init_resource::<ShrinkPolicy>()
+                .init_resource::<BatchReorder>()
+                .add_plugins((
+                    ShapeMaterialPlugin,
+                    ShapeDiagnosticsPlugin,
+                    ShapeOpaquePlugin,
+                    ShapeTrailPlugin,
+                    ExtractResourcePlugin::<ShapeDebug>::default(),
+                    ExtractResourcePlugin::<BatchLimits>::default(),
+                    ExtractResourcePlugin::<ShrinkPolicy>::default(),
+                    ExtractResourcePlugin::<BatchReorder>::default(),
+                ));
+        }
+
+        app.add_plugins(ShapeStatsPlugin::<T>::default())
+            .add_systems(Update, check_shader::<T>);
+
+        self.track_loading.apply(app);
 
         if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            let rank = render_app.world.get_resource_or_insert_with(ShapeOrder::default).register();
+            if rank == 0 {
+                render_app.add_systems(Render, order_requests.in_set(ShapeSystems::OrderVertices));
+            }
+
             render_app
+                .insert_resource(OrderRank::<T>::new(rank))
                 .init_resource::<SpecializedRenderPipelines<ShapePipeline<T>>>()
                 .init_resource::<Requests<T>>()
                 .init_resource::<Batch<T>>()
+                .init_resource::<RetainedBatch<T>>()
                 .init_resource::<DrawLayer<T>>()
                 .add_render_command::<Transparent2d, DrawShapes<T>>()
+                .add_render_command::<ShapeOpaque2d, DrawShapes<T>>()
+                .add_render_command::<ShapeTrail2d, DrawShapes<T>>()
                 .configure_sets(
                     Render,
                     (
-                        (ShapeSystems::QueueShaper, ShapeSystems::QueueVertices).in_set(RenderSet::Queue),
-                        ShapeSystems::QueueVertices.after_ignore_deferred(ShapeSystems::QueueShaper),
+                        (
+                            ShapeSystems::QueueShaper,
+                            ShapeSystems::QueueSdf,
+                            ShapeSystems::SortVertices,
+                            ShapeSystems::OrderVertices,
+                            ShapeSystems::QueueVertices,
+                        )
+                            .in_set(RenderSet::Queue),
+                        ShapeSystems::QueueSdf.after_ignore_deferred(ShapeSystems::QueueShaper),
+                        ShapeSystems::SortVertices.after_ignore_deferred(ShapeSystems::QueueSdf),
+                        ShapeSystems::OrderVertices.after_ignore_deferred(ShapeSystems::SortVertices),
+                        ShapeSystems::QueueVertices.after_ignore_deferred(ShapeSystems::OrderVertices),
                         ShapeSystems::PrepareBatch.in_set(RenderSet::Prepare),
                         ShapeSystems::PrepareBindGroup.in_set(RenderSet::PrepareBindGroups),
                     ),
@@ -83,6 +162,7 @@ impl<T: Vertex> Plugin for ShapePlugin<T> {
                 .add_systems(
                     Render,
                     (
+                        sort_requests::<T>.in_set(ShapeSystems::SortVertices),
                         queue_vertices::<T>.in_set(ShapeSystems::QueueVertices),
                         prepare_vertices_batch::<T>.in_set(ShapeSystems::PrepareBatch),
                         prepare_vertices_bind_group::<T>.in_set(ShapeSystems::PrepareBindGroup),
@@ -92,8 +172,17 @@ impl<T: Vertex> Plugin for ShapePlugin<T> {
     }
 
     fn finish(&self, app: &mut App) {
+        let shader = ShapeShader::<T>::new(match &self.shader {
+            ShapeShaderSource::Path(path) => app.world.resource::<AssetServer>().load(path.clone()),
+            ShapeShaderSource::Handle(handle) => handle.clone(),
+        });
+        app.insert_resource(shader.clone());
+
         if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
-            render_app.init_resource::<ShapePipeline<T>>();
+            render_app
+                .insert_resource(shader)
+                .init_resource::<PrimitiveMeshes>()
+                .init_resource::<ShapePipeline<T>>();
         }
     }
 }
@@ -0,0 +1,270 @@
+use std::{f32::consts::FRAC_1_PI, ops::Range};
+
+use bevy::{
+    core_pipeline::core_2d::{
+        graph::{Core2d, Node2d},
+        Camera2d,
+    },
+    ecs::query::QueryItem,
+    prelude::*,
+    render::{
+        camera::ExtractedCamera,
+        render_graph::{NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel, ViewNode, ViewNodeRunner},
+        render_phase::{
+            sort_phase_system, CachedRenderPipelinePhaseItem, DrawFunctionId, DrawFunctions, PhaseItem, RenderPhase,
+        },
+        render_resource::{
+            CachedRenderPipelineId, CompareFunction, DepthBiasState, DepthStencilState, Extent3d, LoadOp, Operations,
+            RenderPassDepthStencilAttachment, RenderPassDescriptor, StencilState, StoreOp, TextureDescriptor,
+            TextureDimension, TextureFormat, TextureUsages,
+        },
+        renderer::{RenderContext, RenderDevice},
+        texture::{CachedTexture, TextureCache},
+        view::ViewTarget,
+        Extract, ExtractSchedule, Render, RenderApp, RenderSet,
+    },
+    utils::{nonmax::NonMaxU32, FloatOrd},
+};
+
+/// Draws shapes whose key is [`opaque`](crate::shape::vertex::VertexKey::opaque) front-to-back with depth testing,
+/// before the [`Transparent2d`](bevy::core_pipeline::core_2d::Transparent2d) main pass of every 2D camera; added by the
+/// first [`ShapePlugin`].
+///
+/// Opaque shapes thus always end up beneath transparent ones, sprites included, regardless of their layers. Among
+/// themselves, their layers become depth, so hidden fragments are rejected before shading instead of being overdrawn.
+///
+/// [`ShapePlugin`]: crate::shape::ShapePlugin
+pub struct ShapeOpaquePlugin;
+impl Plugin for ShapeOpaquePlugin {
+    fn build(&self, app: &mut App) {
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .init_resource::<DrawFunctions<ShapeOpaque2d>>()
+                .add_systems(ExtractSchedule, extract_opaque_phases)
+                .add_systems(
+                    Render,
+                    (
+                        sort_phase_system::<ShapeOpaque2d>.in_set(RenderSet::PhaseSort),
+                        prepare_opaque_depth.in_set(RenderSet::PrepareResources),
+                    ),
+                )
+                .add_render_graph_node::<ViewNodeRunner<ShapeOpaquePassNode>>(Core2d, ShapeOpaquePass)
+                .add_render_graph_edges(Core2d, (Node2d::MsaaWriteback, ShapeOpaquePass, Node2d::MainPass));
+        }
+    }
+}
+
+/// Maps a layer to depth in `(0, 1)`, greater being closer. Layers within a few hundred of `0.0` keep apart by
+/// thousandths; further ones still keep their order, but tell apart less finely.
+#[inline]
+pub fn layer_depth(layer: f32) -> f32 {
+    0.5 + (layer * 0.01).atan() * FRAC_1_PI
+}
+
+/// Depth format of [`ShapeOpaque2d`] pipelines.
+pub const OPAQUE_DEPTH_FORMAT: TextureFormat = TextureFormat::Depth32Float;
+
+/// Depth state of [`ShapeOpaque2d`] pipelines. Depth is cleared to `0.0`, and fragments on the same layer pass, so
+/// they draw in order like transparent ones do.
+#[inline]
+pub fn opaque_depth_stencil() -> DepthStencilState {
+    DepthStencilState {
+        format: OPAQUE_DEPTH_FORMAT,
+        depth_write_enabled: true,
+        depth_compare: CompareFunction::GreaterEqual,
+        stencil: StencilState::default(),
+        bias: DepthBiasState::default(),
+    }
+}
+
+/// Phase of opaque shape batches, sorted front-to-back by [`layer_depth`].
+pub struct ShapeOpaque2d {
+    pub sort_key: FloatOrd,
+    pub entity: Entity,
+    pub pipeline: CachedRenderPipelineId,
+    pub draw_function: DrawFunctionId,
+    pub batch_range: Range<u32>,
+    pub dynamic_offset: Option<NonMaxU32>,
+}
+
+impl ShapeOpaque2d {
+    /// Sort key drawing batches at `depth` before ones behind them.
+    #[inline]
+    pub fn sort_key(depth: f32) -> FloatOrd {
+        FloatOrd(-depth)
+    }
+}
+
+impl PhaseItem for ShapeOpaque2d {
+    type SortKey = FloatOrd;
+
+    #[inline]
+    fn entity(&self) -> Entity {
+        self.entity
+    }
+
+    #[inline]
+    fn sort_key(&self) -> Self::SortKey {
+        self.sort_key
+    }
+
+    #[inline]
+    fn draw_function(&self) -> DrawFunctionId {
+        self.draw_function
+    }
+
+    #[inline]
+    fn sort(items: &mut [Self]) {
+        radsort::sort_by_key(items, |item| item.sort_key.0);
+    }
+
+    #[inline]
+    fn batch_range(&self) -> &Range<u32> {
+        &self.batch_range
+    }
+
+    #[inline]
+    fn batch_range_mut(&mut self) -> &mut Range<u32> {
+        &mut self.batch_range
+    }
+
+    #[inline]
+    fn dynamic_offset(&self) -> Option<NonMaxU32> {
+        self.dynamic_offset
+    }
+
+    #[inline]
+    fn dynamic_offset_mut(&mut self) -> &mut Option<NonMaxU32> {
+        &mut self.dynamic_offset
+    }
+}
+
+impl CachedRenderPipelinePhaseItem for ShapeOpaque2d {
+    #[inline]
+    fn cached_pipeline(&self) -> CachedRenderPipelineId {
+        self.pipeline
+    }
+}
+
+/// Depth buffer of a view's [`ShapeOpaque2d`] phase, only allocated while the phase has items.
+#[derive(Component)]
+pub struct OpaqueDepth(pub CachedTexture);
+
+fn extract_opaque_phases(mut commands: Commands, cameras: Extract<Query<(Entity, &Camera), With<Camera2d>>>) {
+    for (entity, camera) in &cameras {
+        if camera.is_active {
+            commands.get_or_spawn(entity).insert(RenderPhase::<ShapeOpaque2d>::default());
+        }
+    }
+}
+
+fn prepare_opaque_depth(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    msaa: Res<Msaa>,
+    mut textures: ResMut<TextureCache>,
+    views: Query<(Entity, &ExtractedCamera, &RenderPhase<ShapeOpaque2d>)>,
+) {
+    for (entity, camera, phase) in &views {
+        let Some(size) = camera.physical_target_size.filter(|_| !phase.items.is_empty()) else {
+            continue
+        };
+
+        let texture = textures.get(&device, TextureDescriptor {
+            label: Some("shape_opaque_depth"),
+            size: Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: msaa.samples(),
+            dimension: TextureDimension::D2,
+            format: OPAQUE_DEPTH_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+
+        commands.entity(entity).insert(OpaqueDepth(texture));
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+pub struct ShapeOpaquePass;
+
+#[derive(Default)]
+pub struct ShapeOpaquePassNode;
+impl ViewNode for ShapeOpaquePassNode {
+    type ViewQuery = (
+        &'static ExtractedCamera,
+        &'static RenderPhase<ShapeOpaque2d>,
+        &'static ViewTarget,
+        &'static OpaqueDepth,
+    );
+
+    fn run<'w>(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext<'w>,
+        (camera, phase, target, depth): QueryItem<'w, Self::ViewQuery>,
+        world: &'w World,
+    ) -> Result<(), NodeRunError> {
+        if phase.items.is_empty() {
+            return Ok(())
+        }
+
+        // Being the first pass to write the target, this clears it to the camera's clear color.
+        let mut pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("shape_opaque_pass_2d"),
+            color_attachments: &[Some(target.get_color_attachment())],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &depth.0.default_view,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(0.0),
+                    store: StoreOp::Discard,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        if let Some(viewport) = &camera.viewport {
+            pass.set_camera_viewport(viewport);
+        }
+
+        phase.render(&mut pass, world, graph.view_entity());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shape::opaque::{layer_depth, ShapeOpaque2d};
+
+    #[test]
+    fn layer_depth_is_monotonic() {
+        let near = (-1000..=1000).map(|i| layer_depth(i as f32 * 0.5)).collect::<Vec<_>>();
+        assert!(near.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let far = [-1e4, -1e3, -300.0, 0.0, 300.0, 1e3, 1e4].map(layer_depth);
+        assert!(far.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn layer_depth_stays_in_range() {
+        for layer in [-1e4, -500.0, -1.0, 0.0, 1.0, 500.0, 1e4] {
+            let depth = layer_depth(layer);
+            assert!(depth > 0.0 && depth < 1.0, "{layer} maps to {depth}");
+        }
+
+        assert_eq!(layer_depth(0.0), 0.5);
+    }
+
+    #[test]
+    fn closer_batches_sort_first() {
+        let mut layers = [3.0, -20.0, 150.0, 0.0, 0.5];
+        layers.sort_by_key(|&layer| ShapeOpaque2d::sort_key(layer_depth(layer)));
+        assert_eq!(layers, [150.0, 3.0, 0.5, 0.0, -20.0]);
+    }
+}
@@ -0,0 +1,161 @@
+use std::{marker::PhantomData, sync::Mutex};
+
+use bevy::prelude::*;
+use float_next_after::NextAfter;
+
+use crate::shape::{diagnostic::ShapeErrors, vertex::Vertex};
+
+/// Index of a vertex type in [`ShapeOrder`], assigned in the order their [`ShapePlugin`]s were added. Breaks ties
+/// between requests of different vertex types on the same layer.
+///
+/// [`ShapePlugin`]: crate::shape::ShapePlugin
+#[derive(Resource)]
+pub struct OrderRank<T: Vertex> {
+    pub rank: u32,
+    _marker: PhantomData<fn(T)>,
+}
+
+impl<T: Vertex> OrderRank<T> {
+    #[inline]
+    pub const fn new(rank: u32) -> Self {
+        Self {
+            rank,
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct OrderEntry {
+    layer: f32,
+    rank: u32,
+    index: u32,
+}
+
+/// Placement of a request among the requests of every vertex type this frame.
+#[derive(Copy, Clone)]
+pub struct Order {
+    /// Position in the global order. Requests may only share a batch if their positions are consecutive, i.e. if no
+    /// request of another vertex type has to be drawn between them.
+    pub position: u32,
+    /// Strictly increasing along `position`, so batches keep their order in [`Transparent2d`] regardless of which
+    /// vertex type queues first.
+    ///
+    /// [`Transparent2d`]: bevy::core_pipeline::core_2d::Transparent2d
+    pub sort_key: f32,
+}
+
+/// Sorts the requests of every vertex type together, so that they may interleave by layer.
+#[derive(Resource, Default)]
+pub struct ShapeOrder {
+    ranks: u32,
+    entries: Mutex<Vec<OrderEntry>>,
+    orders: Vec<Vec<Order>>,
+}
+
+impl ShapeOrder {
+    #[inline]
+    pub fn register(&mut self) -> u32 {
+        self.orders.push(Vec::new());
+        self.ranks += 1;
+        self.ranks - 1
+    }
+
+    /// Submits the layers of the requests of vertex type `rank`, in their sorted order.
+    pub fn submit(&self, rank: u32, layers: impl IntoIterator<Item = f32>, errors: &ShapeErrors) {
+        errors
+            .lock(&self.entries, "order_entries")
+            .extend(layers.into_iter().enumerate().map(|(index, layer)| OrderEntry {
+                layer,
+                rank,
+                index: index as u32,
+            }));
+    }
+
+    /// Returns the orders of the requests of vertex type `rank`, indexed the same as they were submitted.
+    #[inline]
+    pub fn orders(&self, rank: u32) -> &[Order] {
+        &self.orders[rank as usize]
+    }
+}
+
+pub fn order_requests(mut order: ResMut<ShapeOrder>, errors: Res<ShapeErrors>) {
+    let ShapeOrder {
+        ref mut entries,
+        ref mut orders,
+        ..
+    } = *order;
+
+    let entries = errors.get_mut(entries, "order_entries");
+    entries.sort_unstable_by(|a, b| {
+        a.layer
+            .total_cmp(&b.layer)
+            .then(a.rank.cmp(&b.rank))
+            .then(a.index.cmp(&b.index))
+    });
+
+    for orders in &mut *orders {
+        orders.clear();
+    }
+
+    let mut next_sort = f32::NEG_INFINITY;
+    for (position, entry) in entries.drain(..).enumerate() {
+        let sort_key = entry.layer.max(next_sort);
+        next_sort = sort_key.next_after(f32::INFINITY);
+
+        // Entries of the same rank come in increasing index, so pushing keeps them indexed the same.
+        orders[entry.rank as usize].push(Order {
+            position: position as u32,
+            sort_key,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{ecs::system::RunSystemOnce, prelude::*};
+
+    use crate::shape::{
+        diagnostic::ShapeErrors,
+        order::{order_requests, ShapeOrder},
+    };
+
+    fn order(layers: [&[f32]; 2]) -> ShapeOrder {
+        let mut world = World::new();
+        let errors = ShapeErrors::default();
+        let mut order = ShapeOrder::default();
+        for layers in layers {
+            let rank = order.register();
+            order.submit(rank, layers.iter().copied(), &errors);
+        }
+
+        world.insert_resource(order);
+        world.insert_resource(errors);
+        world.run_system_once(order_requests);
+        world.remove_resource::<ShapeOrder>().unwrap()
+    }
+
+    #[test]
+    fn equal_layers_get_increasing_keys() {
+        let order = order([&[0.0, 0.0, 0.0, 1e6, 1e6], &[0.0, 0.0, 1e6]]);
+
+        let mut all = [0, 1].iter().flat_map(|&rank| order.orders(rank)).collect::<Vec<_>>();
+        all.sort_by_key(|order| order.position);
+        assert_eq!(
+            all.iter().map(|order| order.position).collect::<Vec<_>>(),
+            (0..8).collect::<Vec<_>>()
+        );
+        assert!(all.windows(2).all(|pair| pair[0].sort_key < pair[1].sort_key));
+        assert!(all.iter().all(|order| order.sort_key >= 0.0));
+    }
+
+    #[test]
+    fn ranks_break_ties_within_a_layer() {
+        let order = order([&[0.0, 0.0, 2.0], &[0.0, 1.0]]);
+        let positions = |rank| order.orders(rank).iter().map(|order| order.position).collect::<Vec<_>>();
+
+        assert_eq!(positions(0), [0, 1, 4]);
+        assert_eq!(positions(1), [2, 3]);
+        assert_eq!(order.orders(1)[1].sort_key, 1.0);
+    }
+}
@@ -1,4 +1,4 @@
-use std::{marker::PhantomData, sync::Mutex};
+use std::{marker::PhantomData, mem, sync::Mutex};
 
 use bevy::{
     core_pipeline::core_2d::Transparent2d,
@@ -11,41 +11,91 @@ use bevy::{
     },
     prelude::*,
     render::{
-        mesh::PrimitiveTopology,
+        extract_resource::ExtractResource,
+        render_asset::RenderAssets,
         render_phase::{
             DrawFunctions, PhaseItem, RenderCommand, RenderCommandResult, RenderPhase, SetItemPipeline, TrackedRenderPass,
         },
         render_resource::{
-            binding_types::uniform_buffer, BindGroup, BindGroupEntries, BindGroupLayout, BlendState, BufferAddress,
-            BufferUsages, BufferVec, ColorTargetState, ColorWrites, FragmentState, FrontFace, IndexFormat, MultisampleState,
-            PipelineCache, PolygonMode, PrimitiveState, RenderPipelineDescriptor, ShaderStages, SpecializedRenderPipeline,
-            SpecializedRenderPipelines, TextureFormat, VertexBufferLayout, VertexState, VertexStepMode,
+            binding_types::uniform_buffer, BindGroup, BindGroupEntries, BindGroupLayout, BlendComponent, BlendFactor,
+            BlendOperation, BlendState, BufferAddress, BufferUsages, CachedRenderPipelineId, ColorTargetState, ColorWrites,
+            DynamicUniformBuffer, FragmentState, FrontFace, IndexFormat, MultisampleState, PipelineCache, PolygonMode,
+            PrimitiveState, RenderPipelineDescriptor, ShaderStages, ShaderType, SpecializedRenderPipeline,
+            SpecializedRenderPipelines, TextureFormat, VertexBufferLayout, VertexState, VertexStepMode, WgpuFeatures,
         },
         renderer::{RenderDevice, RenderQueue},
         texture::BevyDefault,
         view::{ExtractedView, ViewTarget, ViewUniform, ViewUniformOffset, ViewUniforms},
     },
-    utils::FloatOrd,
+    utils::{FloatOrd, HashSet},
 };
 
-use crate::{
-    shape::vertex::{DrawLayer, Request, Vertex, VertexKey},
-    util::FloatExt,
+use crate::shape::{
+    buffer::{FrameBuffer, ShrinkPolicy},
+    debug::ShapeDebug,
+    diagnostic::{ShapeError, ShapeErrors, ShapeStat, ShapeStats},
+    material::{ShapeMaterial, ShapeMaterialLayout},
+    opaque::{layer_depth, opaque_depth_stencil, ShapeOpaque2d},
+    order::{Order, OrderRank, ShapeOrder},
+    primitive::{MeshVertex, Primitive, PrimitiveMeshes},
+    retain::{RetainedBatch, RetainedRequest},
+    trail::{ExtractedAfterimage, ShapeTrail2d},
+    uniform::BatchUniform,
+    vertex::{DrawLayer, InstanceRequest, Request, Topology, Vertex, VertexKey},
 };
 
+/// The strong handle to [`Vertex::SHADER_SOURCE`], in both the main and the render world. Pipelines are recompiled
+/// whenever the shader asset changes.
+#[derive(Resource)]
+pub struct ShapeShader<T: Vertex> {
+    pub handle: Handle<Shader>,
+    _marker: PhantomData<fn(T)>,
+}
+
+impl<T: Vertex> ShapeShader<T> {
+    #[inline]
+    pub const fn new(handle: Handle<Shader>) -> Self {
+        Self {
+            handle,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Vertex> Clone for ShapeShader<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self::new(self.handle.clone())
+    }
+}
+
 pub struct ShapePipeline<T: Vertex> {
+    shader: Handle<Shader>,
     view_layout: BindGroupLayout,
+    uniform_layout: BindGroupLayout,
+    material_layout: BindGroupLayout,
+    polygon_mode_line: bool,
     _marker: PhantomData<fn(T)>,
 }
 
 impl<T: Vertex> Resource for ShapePipeline<T> {}
 impl<T: Vertex> FromWorld for ShapePipeline<T> {
     fn from_world(world: &mut World) -> Self {
+        world.init_resource::<ShapeMaterialLayout>();
+        let shader = world.resource::<ShapeShader<T>>().handle.clone();
+        let material_layout = world.resource::<ShapeMaterialLayout>().0.clone();
+
         let device = SystemState::<Res<RenderDevice>>::new(world).get_mut(world);
         Self {
+            shader,
             view_layout: device.create_bind_group_layout("draw_view_layout", &[
                 uniform_buffer::<ViewUniform>(true).build(0, ShaderStages::VERTEX)
             ]),
+            uniform_layout: device.create_bind_group_layout("draw_uniform_layout", &[
+                uniform_buffer::<BatchUniform>(true).build(0, ShaderStages::VERTEX_FRAGMENT)
+            ]),
+            material_layout,
+            polygon_mode_line: device.features().contains(WgpuFeatures::POLYGON_MODE_LINE),
             _marker: PhantomData,
         }
     }
@@ -55,6 +105,16 @@ impl<T: Vertex> FromWorld for ShapePipeline<T> {
 pub struct ShapeCommonKey {
     pub hdr: bool,
     pub msaa: u8,
+    pub instanced: bool,
+    /// Fragment shader of the key's [`ShapeMaterial`], if any.
+    pub material: Option<AssetId<Shader>>,
+    pub topology: Topology,
+    pub index_format: IndexFormat,
+    /// See [`ShapeDebug`].
+    pub wireframe: bool,
+    pub overdraw: bool,
+    /// Whether the pipeline draws into [`ShapeOpaque2d`], see [`VertexKey::opaque`].
+    pub opaque: bool,
 }
 
 impl<T: Vertex> SpecializedRenderPipeline for ShapePipeline<T> {
@@ -62,39 +122,79 @@ impl<T: Vertex> SpecializedRenderPipeline for ShapePipeline<T> {
 
     fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
         let (common, key) = key;
+        let (mut shader_defs, buffers) = match common.instanced {
+            false => (Vec::new(), vec![VertexBufferLayout {
+                array_stride: size_of::<T>() as BufferAddress,
+                step_mode: VertexStepMode::Vertex,
+                attributes: T::LAYOUT.into(),
+            }]),
+            true => (vec!["INSTANCED".into()], vec![
+                MeshVertex::buffer_layout(),
+                VertexBufferLayout {
+                    array_stride: size_of::<T::Instance>() as BufferAddress,
+                    step_mode: VertexStepMode::Instance,
+                    attributes: T::INSTANCE_LAYOUT.into(),
+                },
+            ]),
+        };
+
+        shader_defs.extend(key.shader_defs());
+        if common.overdraw {
+            shader_defs.push("DEBUG_OVERDRAW".into());
+        }
+        if common.opaque {
+            shader_defs.push("OPAQUE".into());
+        }
+        if common.hdr {
+            shader_defs.push("HDR".into());
+        }
+
+        let polygon_mode = match (common.wireframe, self.polygon_mode_line) {
+            (false, _) => PolygonMode::Fill,
+            (true, true) => PolygonMode::Line,
+            (true, false) => {
+                warn!("wireframe shapes need `POLYGON_MODE_LINE`, which this device doesn't support");
+                PolygonMode::Fill
+            }
+        };
+
+        let mut layout = vec![self.view_layout.clone(), self.uniform_layout.clone()];
+        let fragment = match common.material {
+            None => self.shader.clone(),
+            Some(shader) => {
+                layout.push(self.material_layout.clone());
+                Handle::Weak(shader)
+            }
+        };
+
         let mut desc = RenderPipelineDescriptor {
             label: Some("draw_pipeline".into()),
-            layout: [self.view_layout.clone()].into(),
+            layout,
             push_constant_ranges: Vec::new(),
             vertex: VertexState {
-                shader: T::SHADER,
-                shader_defs: Vec::new(),
+                shader: self.shader.clone(),
+                shader_defs: shader_defs.clone(),
                 entry_point: "vertex_main".into(),
-                buffers: [VertexBufferLayout {
-                    array_stride: size_of::<T>() as BufferAddress,
-                    step_mode: VertexStepMode::Vertex,
-                    attributes: T::LAYOUT.into(),
-                }]
-                .into(),
+                buffers,
             },
             primitive: PrimitiveState {
-                topology: PrimitiveTopology::TriangleList,
-                strip_index_format: None,
+                topology: common.topology.primitive_topology(),
+                strip_index_format: common.topology.strip_index_format(common.index_format),
                 front_face: FrontFace::Ccw,
                 cull_mode: None,
                 unclipped_depth: false,
-                polygon_mode: PolygonMode::Fill,
+                polygon_mode,
                 conservative: false,
             },
-            depth_stencil: None,
+            depth_stencil: common.opaque.then(opaque_depth_stencil),
             multisample: MultisampleState {
                 count: 1 << common.msaa,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
             fragment: Some(FragmentState {
-                shader: T::SHADER,
-                shader_defs: Vec::new(),
+                shader: fragment,
+                shader_defs,
                 entry_point: "fragment_main".into(),
                 targets: [Some(ColorTargetState {
                     format: match common.hdr {
@@ -109,6 +209,24 @@ impl<T: Vertex> SpecializedRenderPipeline for ShapePipeline<T> {
         };
 
         key.specialize(&mut desc);
+        if common.overdraw {
+            for target in desc
+                .fragment
+                .iter_mut()
+                .flat_map(|fragment| fragment.targets.iter_mut().flatten())
+            {
+                target.write_mask = ColorWrites::ALL;
+                target.blend = Some(BlendState {
+                    color: BlendComponent {
+                        src_factor: BlendFactor::One,
+                        dst_factor: BlendFactor::One,
+                        operation: BlendOperation::Add,
+                    },
+                    alpha: BlendComponent::OVER,
+                });
+            }
+        }
+
         desc
     }
 }
@@ -122,114 +240,592 @@ pub struct BatchBindGroup<T: Vertex> {
 #[derive(Resource)]
 pub struct Requests<T: Vertex> {
     pub values: Mutex<Vec<Request<T>>>,
+    pub instances: Mutex<Vec<InstanceRequest<T>>>,
+    pub retained: Mutex<Vec<RetainedRequest<T::Key>>>,
+    /// Every request of this frame, sorted by layer.
+    pub queued: Vec<Queued<T>>,
 }
 
 impl<T: Vertex> Default for Requests<T> {
     #[inline]
     fn default() -> Self {
-        Self { values: default() }
+        Self {
+            values: default(),
+            instances: default(),
+            retained: default(),
+            queued: Vec::new(),
+        }
+    }
+}
+
+/// Limits of the geometry drawn by a single batch, applied to requests drawn this frame; retained geometry always uses
+/// 32-bit indices.
+#[derive(Resource, ExtractResource, Copy, Clone, Debug)]
+pub struct BatchLimits {
+    /// Vertices a batch may address. Requests past it start a new batch, unless they exceed it on their own.
+    pub max_vertices: u32,
+    /// Whether batches that address at most `u16::MAX` vertices use 16-bit indices.
+    pub u16_indices: bool,
+}
+
+impl Default for BatchLimits {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            max_vertices: u16::MAX as u32,
+            u16_indices: true,
+        }
+    }
+}
+
+impl BatchLimits {
+    /// Places `len` vertices after the `written` ones of a frame, whose current chunk starts at `chunk_start`. Returns
+    /// the start of the chunk they go in, which is a new one at `written` once the current one would pass
+    /// `max_vertices`, and the format of their indices relative to it.
+    pub fn chunk(&self, chunk_start: u32, written: u32, len: u32) -> (u32, IndexFormat) {
+        let chunk_start = match written - chunk_start + len > self.max_vertices {
+            false => chunk_start,
+            true => written,
+        };
+
+        match self.u16_indices && written - chunk_start + len <= u16::MAX as u32 {
+            false => (chunk_start, IndexFormat::Uint32),
+            true => (chunk_start, IndexFormat::Uint16),
+        }
     }
 }
 
 #[derive(Resource)]
 pub struct Batch<T: Vertex> {
-    pub vertices: BufferVec<T>,
-    pub indices: BufferVec<u32>,
+    pub vertices: FrameBuffer<T>,
+    pub indices: FrameBuffer<u32>,
+    pub indices_u16: FrameBuffer<u16>,
+    pub instances: FrameBuffer<T::Instance>,
+    pub uniforms: DynamicUniformBuffer<BatchUniform>,
+    pub uniform_group: Option<BindGroup>,
 }
 
 impl<T: Vertex> Default for Batch<T> {
     #[inline]
     fn default() -> Self {
         Self {
-            vertices: BufferVec::new(BufferUsages::VERTEX),
-            indices: BufferVec::new(BufferUsages::INDEX),
+            vertices: FrameBuffer::new("frame_vertex_buffer", BufferUsages::VERTEX),
+            indices: FrameBuffer::new("frame_index_buffer", BufferUsages::INDEX),
+            indices_u16: FrameBuffer::new("frame_index_u16_buffer", BufferUsages::INDEX),
+            instances: FrameBuffer::new("frame_instance_buffer", BufferUsages::VERTEX),
+            uniforms: default(),
+            uniform_group: None,
         }
     }
 }
 
+/// A range of indices to draw, or of instances if `primitive` is set.
 #[derive(Component, Copy, Clone)]
 pub struct BatchSection {
+    retained: bool,
+    topology: Topology,
+    primitive: Option<Primitive>,
+    /// First vertex the indices are relative to.
+    base_vertex: u32,
+    index_format: IndexFormat,
     start: u32,
     end: u32,
+    /// Dynamic offset into [`Batch::uniforms`].
+    uniform: u32,
+    material: Option<AssetId<ShapeMaterial>>,
+}
+
+pub enum Queued<T: Vertex> {
+    Frame(Request<T>),
+    Instance(InstanceRequest<T>),
+    Retained(RetainedRequest<T::Key>),
+}
+
+impl<T: Vertex> Queued<T> {
+    #[inline]
+    pub fn layer(&self) -> f32 {
+        match self {
+            Self::Frame(request) => request.layer,
+            Self::Instance(request) => request.layer,
+            Self::Retained(request) => request.layer,
+        }
+    }
+
+    #[inline]
+    pub fn key(&self) -> &T::Key {
+        match self {
+            Self::Frame(request) => &request.key,
+            Self::Instance(request) => &request.key,
+            Self::Retained(request) => &request.key,
+        }
+    }
+
+    #[inline]
+    pub fn bounds(&self) -> Option<Rect> {
+        if !self.key().world_space() {
+            return None
+        }
+
+        match self {
+            Self::Frame(request) => T::bounds(&request.vertices),
+            Self::Instance(request) => T::instance_bounds(&request.instance, request.primitive),
+            Self::Retained(request) => request.bounds,
+        }
+    }
+
+    /// Whether both requests may be drawn in the same batch, given that they end up next to each other.
+    #[inline]
+    pub fn batches_with(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Frame(a), Self::Frame(b)) => a.topology == b.topology && a.key == b.key,
+            (Self::Instance(a), Self::Instance(b)) => a.primitive == b.primitive && a.key == b.key,
+            (Self::Retained(a), Self::Retained(b)) => {
+                a.topology == b.topology && a.primitive == b.primitive && a.key == b.key
+            }
+            _ => false,
+        }
+    }
+}
+
+/// How [`sort_requests`] may reorder requests, so that requests that batch together end up next to each other.
+#[derive(Resource, ExtractResource, Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum BatchReorder {
+    Disabled,
+    /// Only moves requests back past ones whose bounds they don't overlap, across layers too, so the result looks the
+    /// same. Requests without bounds stay in place.
+    #[default]
+    NonOverlapping,
+    /// Groups requests on the same layer regardless of overlap, leaving the drawing order within a layer unspecified.
+    EqualLayers,
 }
 
+impl BatchReorder {
+    /// How far back a request looks for one it batches with.
+    pub const WINDOW: usize = 64;
+
+    /// Reorders `queued`, which has to be sorted by layer already. Afterwards, only the layers they're drawn at are, see
+    /// [`drawn_layers`](Self::drawn_layers). `order` and `bounds` are scratch space.
+    pub fn apply<T: Vertex>(self, queued: &mut [Queued<T>], order: &mut Vec<usize>, bounds: &mut Vec<Option<Rect>>) {
+        if self == Self::Disabled || queued.len() < 2 {
+            return
+        }
+
+        bounds.clear();
+        if self == Self::NonOverlapping {
+            bounds.extend(queued.iter().map(Queued::bounds));
+        }
+
+        let disjoint = |a: usize, b: usize| match (bounds[a], bounds[b]) {
+            (Some(a), Some(b)) => a.intersect(b).is_empty(),
+            _ => false,
+        };
+
+        // `order[i]` is the index of the request that goes to `i`.
+        order.clear();
+        for i in 0..queued.len() {
+            let mut at = order.len();
+            for k in (order.len().saturating_sub(Self::WINDOW)..order.len()).rev() {
+                let other = order[k];
+                if queued[other].batches_with(&queued[i]) {
+                    at = k + 1;
+                    break
+                }
+
+                let passes = match self {
+                    Self::NonOverlapping => disjoint(other, i),
+                    _ => queued[other].layer() == queued[i].layer(),
+                };
+
+                if !passes {
+                    break
+                }
+            }
+
+            order.insert(at, i);
+        }
+
+        // Applies the permutation in place, one cycle at a time.
+        for i in 0..order.len() {
+            let mut cur = i;
+            while order[cur] != usize::MAX {
+                let src = mem::replace(&mut order[cur], usize::MAX);
+                if src == i {
+                    break
+                }
+
+                queued.swap(cur, src);
+                cur = src;
+            }
+        }
+    }
+
+    /// The layers requests reordered by [`apply`](Self::apply) are drawn at, in order. Requests moved back past ones on
+    /// lower layers are drawn at the lowest of them, so other vertex types and sprites in between may end up in front.
+    pub fn drawn_layers<T: Vertex>(queued: &[Queued<T>], layers: &mut Vec<f32>) {
+        layers.clear();
+        layers.extend(queued.iter().rev().scan(f32::INFINITY, |min, request| {
+            *min = min.min(request.layer());
+            Some(*min)
+        }));
+        layers.reverse();
+    }
+}
+
+/// Positions, bounds and drawn layers of the requests being sorted.
+type SortScratch = (Vec<usize>, Vec<Option<Rect>>, Vec<f32>);
+
+#[allow(clippy::too_many_arguments)]
+pub fn sort_requests<T: Vertex>(
+    mut requests: ResMut<Requests<T>>,
+    mut retained_batch: ResMut<RetainedBatch<T>>,
+    layer: Res<DrawLayer<T>>,
+    rank: Res<OrderRank<T>>,
+    order: Res<ShapeOrder>,
+    errors: Res<ShapeErrors>,
+    stats: Res<ShapeStats<T>>,
+    reorder: Res<BatchReorder>,
+    mut scratch: Local<SortScratch>,
+) {
+    // Every retained shaper still alive has either reused or replaced its geometry by now.
+    retained_batch.evict();
+
+    let Requests {
+        values,
+        instances,
+        retained,
+        queued,
+    } = &mut *requests;
+    queued.extend(errors.get_mut(values, "requests").drain(..).map(Queued::Frame));
+    queued.extend(errors.get_mut(instances, "instance_requests").drain(..).map(Queued::Instance));
+    queued.extend(errors.get_mut(retained, "retained_requests").drain(..).map(Queued::Retained));
+    radsort::sort_by_key(queued, Queued::layer);
+
+    let (positions, bounds, layers) = &mut *scratch;
+    reorder.apply(queued, positions, bounds);
+    BatchReorder::drawn_layers(queued, layers);
+    stats.add(ShapeStat::Requests, queued.len() as u64);
+
+    let offset = layer.layer;
+    order.submit(rank.rank, layers.iter().map(|&layer| offset + layer), &errors);
+}
+
+type ViewPhases = (
+    &'static mut RenderPhase<Transparent2d>,
+    Option<&'static mut RenderPhase<ShapeOpaque2d>>,
+    Option<(&'static mut RenderPhase<ShapeTrail2d>, &'static ExtractedAfterimage)>,
+    &'static ExtractedView,
+);
+
+type DrawFunctionsParam<'w> = (
+    Res<'w, DrawFunctions<Transparent2d>>,
+    Res<'w, DrawFunctions<ShapeOpaque2d>>,
+    Res<'w, DrawFunctions<ShapeTrail2d>>,
+);
+
+#[allow(clippy::too_many_arguments)]
 pub fn queue_vertices<T: Vertex>(
     mut commands: Commands,
     msaa: Res<Msaa>,
     mut batch: ResMut<Batch<T>>,
-    layer: Res<DrawLayer<T>>,
     mut requests: ResMut<Requests<T>>,
+    rank: Res<OrderRank<T>>,
+    order: Res<ShapeOrder>,
     draw_pipeline: Res<ShapePipeline<T>>,
     mut pipelines: ResMut<SpecializedRenderPipelines<ShapePipeline<T>>>,
     pipeline_cache: Res<PipelineCache>,
-    draw_functions: Res<DrawFunctions<Transparent2d>>,
-    mut views: Query<(&mut RenderPhase<Transparent2d>, &ExtractedView)>,
+    draw_functions: DrawFunctionsParam,
+    materials: Res<RenderAssets<ShapeMaterial>>,
+    time: Res<Time>,
+    stats: Res<ShapeStats<T>>,
+    (debug, limits, layer): (Res<ShapeDebug>, Res<BatchLimits>, Res<DrawLayer<T>>),
+    mut specialized: Local<HashSet<CachedRenderPipelineId>>,
+    mut views: Query<ViewPhases>,
 ) {
-    let mut layer = layer.layer;
-
+    let (draw_functions, opaque_draw_functions, trail_draw_functions) = draw_functions;
     let draw_function = draw_functions.read().id::<DrawShapes<T>>();
+    let opaque_draw_function = opaque_draw_functions.read().id::<DrawShapes<T>>();
+    let trail_draw_function = trail_draw_functions.read().id::<DrawShapes<T>>();
+    let offset = layer.layer;
     let msaa = msaa.samples().trailing_zeros() as u8;
-
-    let requests = requests.values.get_mut().unwrap();
-    radsort::sort_by_key(requests, |req| req.layer);
+    let time = time.elapsed_seconds_wrapped();
+    let orders = order.orders(rank.rank);
 
     let Batch {
         ref mut vertices,
         ref mut indices,
+        ref mut indices_u16,
+        instances: ref mut instance_buffer,
+        ref mut uniforms,
+        ..
     } = *batch;
     vertices.clear();
     indices.clear();
+    indices_u16.clear();
+    instance_buffer.clear();
+    uniforms.clear();
+
+    // Batches in the layers of an afterimage are also drawn into its buffer, so mustn't take in any outside of them.
+    let trails = views
+        .iter()
+        .filter_map(|(.., trail, _)| trail.map(|(_, afterimage)| afterimage.layers.clone()))
+        .collect::<Vec<_>>();
+    let in_trails = |layer: f32| trails.iter().map(move |layers| layers.contains(&(offset + layer)));
+
+    // Batches sort by their first request's layer, so they interleave with sprites and meshes by their `Transform.z`
+    // and with batches of other vertex types. Opaque batches instead sort front-to-back by their layer's depth.
+    let mut uniform_bytes = 0;
+    let mut batch_index = 0;
+    let mut add = |sort_key: f32, layer: f32, mut section: BatchSection, key: T::Key| {
+        section.material = key.material();
+        let material = match section.material {
+            None => None,
+            Some(id) => match materials.get(id) {
+                Some(material) => Some(material.shader).filter(|_| !debug.overdraw),
+                None => return,
+            },
+        };
+
+        let opaque = key.opaque();
+        let depth = if opaque { layer_depth(offset + layer) } else { 0.0 };
+
+        let mut uniform = BatchUniform {
+            time,
+            depth,
+            ..key.uniform()
+        };
+        if debug.batch_tint {
+            uniform.tint *= ShapeDebug::batch_color(batch_index);
+        }
+
+        batch_index += 1;
+        section.uniform = uniforms.push(&uniform);
+        uniform_bytes = section.uniform as u64 + BatchUniform::min_size().get();
+        stats.add(ShapeStat::Batches, 1);
+
+        let key = key.pipeline_key();
+        for (mut phase, opaque_phase, trail_phase, view) in &mut views {
+            // Views without an opaque phase still draw opaque batches, as transparent ones sorted by their layer.
+            let opaque = opaque && opaque_phase.is_some();
+            let common = ShapeCommonKey {
+                hdr: view.hdr,
+                msaa,
+                instanced: section.primitive.is_some(),
+                material,
+                topology: section.topology,
+                index_format: section.index_format,
+                wireframe: debug.wireframe,
+                overdraw: debug.overdraw,
+                opaque,
+            };
+
+            let mut specialize = |common| {
+                let pipeline = pipelines.specialize(&pipeline_cache, &draw_pipeline, (common, key.clone()));
+                if specialized.insert(pipeline) {
+                    stats.add(ShapeStat::Specializations, 1);
+                }
 
-    let mut add = |layer: &mut f32, start: u32, end: u32, hdr: bool, key: T::Key| Transparent2d {
-        sort_key: FloatOrd(layer.next_swap()),
-        entity: commands.spawn(BatchSection { start, end }).id(),
-        pipeline: pipelines.specialize(&pipeline_cache, &draw_pipeline, (ShapeCommonKey { hdr, msaa }, key)),
-        draw_function,
-        batch_range: 0..1,
-        dynamic_offset: None,
+                pipeline
+            };
+
+            // Afterimage buffers aren't multisampled, and have no depth to test against.
+            if let Some((mut trail_phase, afterimage)) = trail_phase {
+                if afterimage.layers.contains(&(offset + layer)) {
+                    trail_phase.add(ShapeTrail2d {
+                        sort_key: FloatOrd(sort_key),
+                        entity: commands.spawn(section).id(),
+                        pipeline: specialize(ShapeCommonKey {
+                            msaa: 0,
+                            opaque: false,
+                            ..common
+                        }),
+                        draw_function: trail_draw_function,
+                        batch_range: 0..1,
+                        dynamic_offset: None,
+                    });
+                }
+            }
+
+            let pipeline = specialize(common);
+
+            match opaque_phase.filter(|_| opaque) {
+                Some(mut opaque_phase) => opaque_phase.add(ShapeOpaque2d {
+                    sort_key: ShapeOpaque2d::sort_key(depth),
+                    entity: commands.spawn(section).id(),
+                    pipeline,
+                    draw_function: opaque_draw_function,
+                    batch_range: 0..1,
+                    dynamic_offset: None,
+                }),
+                None => phase.add(Transparent2d {
+                    sort_key: FloatOrd(sort_key),
+                    entity: commands.spawn(section).id(),
+                    pipeline,
+                    draw_function,
+                    batch_range: 0..1,
+                    dynamic_offset: None,
+                }),
+            }
+        }
     };
 
-    let mut prev = None;
-    for mut request in requests.drain(..) {
-        let base_offset = indices.len() as u32;
-
-        let new_key = request.key;
-        match prev.take() {
-            None => prev = Some((base_offset, new_key)),
-            Some((prev_offset, prev_key)) => {
-                if &prev_key != &new_key {
-                    for (mut phase, view) in &mut views {
-                        phase.add(add(&mut layer, prev_offset, base_offset, view.hdr, prev_key.clone()));
+    let mut chunk_start = 0;
+    let mut prev = None::<(BatchSection, T::Key, Order, u32, f32)>;
+    for (request, &order) in requests.queued.drain(..).zip(orders) {
+        let layer = request.layer();
+        let (section, new_key) = match request {
+            Queued::Frame(mut request) => {
+                stats.add(ShapeStat::Vertices, request.vertices.len() as u64);
+                stats.add(ShapeStat::Indices, request.indices.len() as u64);
+
+                // Split into a new chunk of vertices once the current one would pass the limit; its indices are then
+                // relative to the chunk's start, so they may fit in 16 bits.
+                let index_format;
+                (chunk_start, index_format) =
+                    limits.chunk(chunk_start, vertices.len() as u32, request.vertices.len() as u32);
+
+                let base_index = vertices.len() as u32 - chunk_start;
+                vertices.values_mut().append(&mut request.vertices);
+
+                let rebased = request.topology.rebase(request.indices, base_index);
+                let (start, end) = match index_format {
+                    IndexFormat::Uint32 => {
+                        let start = indices.len() as u32;
+                        indices.values_mut().extend(rebased);
+                        (start, indices.len() as u32)
                     }
+                    IndexFormat::Uint16 => {
+                        let start = indices_u16.len() as u32;
+                        indices_u16.values_mut().extend(rebased.map(|i| {
+                            if i == Topology::RESTART {
+                                u16::MAX
+                            } else {
+                                i as u16
+                            }
+                        }));
+                        (start, indices_u16.len() as u32)
+                    }
+                };
+
+                (
+                    BatchSection {
+                        retained: false,
+                        topology: request.topology,
+                        primitive: None,
+                        base_vertex: chunk_start,
+                        index_format,
+                        start,
+                        end,
+                        uniform: 0,
+                        material: None,
+                    },
+                    request.key,
+                )
+            }
+            Queued::Instance(request) => {
+                stats.add(ShapeStat::Instances, 1);
+                let start = instance_buffer.push(request.instance) as u32;
+                (
+                    BatchSection {
+                        retained: false,
+                        topology: Topology::TriangleList,
+                        primitive: Some(request.primitive),
+                        base_vertex: 0,
+                        index_format: IndexFormat::Uint32,
+                        start,
+                        end: start + 1,
+                        uniform: 0,
+                        material: None,
+                    },
+                    request.key,
+                )
+            }
+            Queued::Retained(request) => {
+                let stat = match request.primitive {
+                    None => ShapeStat::Indices,
+                    Some(..) => ShapeStat::Instances,
+                };
 
-                    prev = Some((base_offset, new_key));
-                } else {
-                    prev = Some((prev_offset, new_key));
+                stats.add(stat, request.range.len() as u64);
+                (
+                    BatchSection {
+                        retained: true,
+                        topology: request.topology,
+                        primitive: request.primitive,
+                        base_vertex: 0,
+                        index_format: IndexFormat::Uint32,
+                        start: request.range.start,
+                        end: request.range.end,
+                        uniform: 0,
+                        material: None,
+                    },
+                    request.key,
+                )
+            }
+        };
+
+        match prev {
+            // Sections may only merge if they're drawn from the same buffer and are contiguous in it, and if no request
+            // of another vertex type sorts between them. Opaque sections share a depth, so their layers have to map to
+            // the same one, and sections in the layers of an afterimage share its buffer, so have to be in the same
+            // ones.
+            Some((ref mut prev_section, ref prev_key, _, ref mut prev_position, prev_layer))
+                if prev_key == &new_key &&
+                    prev_section.retained == section.retained &&
+                    prev_section.topology == section.topology &&
+                    prev_section.base_vertex == section.base_vertex &&
+                    prev_section.index_format == section.index_format &&
+                    prev_section.primitive == section.primitive &&
+                    prev_section.end == section.start &&
+                    *prev_position + 1 == order.position &&
+                    (!new_key.opaque() || layer_depth(offset + prev_layer) == layer_depth(offset + layer)) &&
+                    in_trails(prev_layer).eq(in_trails(layer)) =>
+            {
+                prev_section.end = section.end;
+                *prev_position = order.position;
+            }
+            _ => {
+                if let Some((prev_section, prev_key, prev_order, _, prev_layer)) =
+                    prev.replace((section, new_key, order, order.position, layer))
+                {
+                    add(prev_order.sort_key, prev_layer, prev_section, prev_key);
                 }
             }
         }
+    }
 
-        let base_index = vertices.len() as u32;
-        vertices.values_mut().append(&mut request.vertices);
-        indices
-            .values_mut()
-            .extend(request.indices.into_iter().map(|i| base_index + i));
+    if let Some((prev_section, prev_key, prev_order, _, prev_layer)) = prev.take() {
+        add(prev_order.sort_key, prev_layer, prev_section, prev_key);
     }
 
-    if let Some((prev_offset, prev_key)) = prev.take() {
-        for (mut phase, view) in &mut views {
-            phase.add(add(&mut layer, prev_offset, indices.len() as u32, view.hdr, prev_key.clone()));
-        }
+    // Buffer writes have to be a multiple of 4 bytes long.
+    if indices_u16.len() % 2 == 1 {
+        indices_u16.push(0);
     }
+
+    stats.add(ShapeStat::BytesUploaded, uniform_bytes);
 }
 
 pub fn prepare_vertices_batch<T: Vertex>(
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
     mut batch: ResMut<Batch<T>>,
+    mut retained_batch: ResMut<RetainedBatch<T>>,
+    stats: Res<ShapeStats<T>>,
+    policy: Res<ShrinkPolicy>,
 ) {
-    batch.vertices.write_buffer(&render_device, &render_queue);
-    batch.indices.write_buffer(&render_device, &render_queue);
+    let (device, queue) = (&render_device, &render_queue);
+    batch.uniforms.write_buffer(device, queue);
+
+    let bytes = batch.vertices.write_buffer(device, queue, &policy) +
+        batch.indices.write_buffer(device, queue, &policy) +
+        batch.indices_u16.write_buffer(device, queue, &policy) +
+        batch.instances.write_buffer(device, queue, &policy) +
+        retained_batch.vertices.write_buffer(device, queue, &policy) +
+        retained_batch.indices.write_buffer(device, queue, &policy) +
+        retained_batch.instances.write_buffer(device, queue, &policy);
+
+    stats.add(ShapeStat::BytesUploaded, bytes);
+    stats.set(ShapeStat::RetainedVertices, retained_batch.vertices.values().len() as u64);
 }
 
 pub fn prepare_vertices_bind_group<T: Vertex>(
@@ -237,8 +833,22 @@ pub fn prepare_vertices_bind_group<T: Vertex>(
     render_device: Res<RenderDevice>,
     view_uniforms: Res<ViewUniforms>,
     pipeline: Res<ShapePipeline<T>>,
+    mut batch: ResMut<Batch<T>>,
     views: Query<Entity, With<ExtractedView>>,
 ) {
+    let Batch {
+        ref uniforms,
+        ref mut uniform_group,
+        ..
+    } = *batch;
+    *uniform_group = uniforms.binding().map(|binding| {
+        render_device.create_bind_group(
+            "draw_uniform_group",
+            &pipeline.uniform_layout,
+            &BindGroupEntries::single(binding),
+        )
+    });
+
     let Some(view_binding) = view_uniforms.uniforms.binding() else {
         return
     };
@@ -255,7 +865,13 @@ pub fn prepare_vertices_bind_group<T: Vertex>(
     }
 }
 
-pub type DrawShapes<T> = (SetItemPipeline, SetBatchBindGroup<T, 0>, DrawBatch<T>);
+pub type DrawShapes<T> = (
+    SetItemPipeline,
+    SetBatchBindGroup<T, 0>,
+    SetBatchUniform<T, 1>,
+    SetMaterialBindGroup<2>,
+    DrawBatch<T>,
+);
 
 pub struct SetBatchBindGroup<T: Vertex, const I: usize> {
     _marker: PhantomData<fn(T)>,
@@ -279,12 +895,82 @@ impl<T: Vertex, P: PhaseItem, const I: usize> RenderCommand<P> for SetBatchBindG
     }
 }
 
+pub struct SetBatchUniform<T: Vertex, const I: usize> {
+    _marker: PhantomData<fn(T)>,
+}
+
+impl<T: Vertex, P: PhaseItem, const I: usize> RenderCommand<P> for SetBatchUniform<T, I> {
+    type Param = (SRes<Batch<T>>, SRes<ShapeErrors>);
+    type ViewQuery = ();
+    type ItemQuery = Read<BatchSection>;
+
+    #[inline]
+    fn render<'w>(
+        _: &P,
+        _: ROQueryItem<'w, Self::ViewQuery>,
+        section: Option<ROQueryItem<'w, Self::ItemQuery>>,
+        (batch, errors): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(section) = section else {
+            return errors.fail(ShapeError::MissingSection)
+        };
+
+        let Some(group) = &batch.into_inner().uniform_group else {
+            return errors.fail(ShapeError::MissingBindGroup {
+                group: "draw_uniform_group",
+            })
+        };
+
+        pass.set_bind_group(I, group, &[section.uniform]);
+        RenderCommandResult::Success
+    }
+}
+
+pub struct SetMaterialBindGroup<const I: usize>;
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetMaterialBindGroup<I> {
+    type Param = (SRes<RenderAssets<ShapeMaterial>>, SRes<ShapeErrors>);
+    type ViewQuery = ();
+    type ItemQuery = Read<BatchSection>;
+
+    #[inline]
+    fn render<'w>(
+        _: &P,
+        _: ROQueryItem<'w, Self::ViewQuery>,
+        section: Option<ROQueryItem<'w, Self::ItemQuery>>,
+        (materials, errors): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(section) = section else {
+            return errors.fail(ShapeError::MissingSection)
+        };
+
+        let Some(id) = section.material else {
+            return RenderCommandResult::Success
+        };
+
+        let Some(material) = materials.into_inner().get(id) else {
+            return errors.fail(ShapeError::MissingBindGroup {
+                group: "shape_material_group",
+            })
+        };
+
+        pass.set_bind_group(I, &material.bind_group, &[]);
+        RenderCommandResult::Success
+    }
+}
+
 pub struct DrawBatch<T: Vertex> {
     _marker: PhantomData<fn(T)>,
 }
 
 impl<T: Vertex, P: PhaseItem> RenderCommand<P> for DrawBatch<T> {
-    type Param = SRes<Batch<T>>;
+    type Param = (
+        SRes<Batch<T>>,
+        SRes<RetainedBatch<T>>,
+        SRes<PrimitiveMeshes>,
+        SRes<ShapeErrors>,
+    );
     type ViewQuery = ();
     type ItemQuery = Read<BatchSection>;
 
@@ -292,26 +978,222 @@ impl<T: Vertex, P: PhaseItem> RenderCommand<P> for DrawBatch<T> {
         _: &P,
         _: ROQueryItem<'w, Self::ViewQuery>,
         section: Option<ROQueryItem<'w, Self::ItemQuery>>,
-        batch: SystemParamItem<'w, '_, Self::Param>,
+        (batch, retained_batch, meshes, errors): SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
         let Some(section) = section else {
-            return RenderCommandResult::Failure
+            return errors.fail(ShapeError::MissingSection)
         };
 
-        let Batch {
-            ref vertices,
-            ref indices,
-        } = batch.into_inner();
+        let (vertices, indices, instances) = match section.retained {
+            false => {
+                let batch = batch.into_inner();
+                let indices = match section.index_format {
+                    IndexFormat::Uint16 => batch.indices_u16.buffer(),
+                    IndexFormat::Uint32 => batch.indices.buffer(),
+                };
 
-        let (Some(vertices), Some(indices)) = (vertices.buffer(), indices.buffer()) else {
-            return RenderCommandResult::Failure
+                (batch.vertices.buffer(), indices, batch.instances.buffer())
+            }
+            true => {
+                let batch = retained_batch.into_inner();
+                (batch.vertices.buffer(), batch.indices.buffer(), batch.instances.buffer())
+            }
         };
 
-        pass.set_vertex_buffer(0, vertices.slice(..));
-        pass.set_index_buffer(indices.slice(..), 0, IndexFormat::Uint32);
-        pass.draw_indexed(section.start..section.end, 0, 0..1);
+        match section.primitive {
+            None => {
+                let Some(vertices) = vertices else {
+                    return errors.fail(ShapeError::EmptyBuffer { buffer: "vertices" })
+                };
+                let Some(indices) = indices else {
+                    return errors.fail(ShapeError::EmptyBuffer { buffer: "indices" })
+                };
+
+                // Indices are relative to the section's chunk, so the binding starts at its first vertex.
+                let offset = section.base_vertex as BufferAddress * size_of::<T>() as BufferAddress;
+                pass.set_vertex_buffer(0, vertices.slice(offset..));
+                pass.set_index_buffer(indices.slice(..), 0, section.index_format);
+                pass.draw_indexed(section.start..section.end, 0, 0..1);
+            }
+            Some(primitive) => {
+                let Some(instances) = instances else {
+                    return errors.fail(ShapeError::EmptyBuffer { buffer: "instances" })
+                };
+
+                let meshes = meshes.into_inner();
+                let (mesh_vertices, mesh_indices) = meshes.range(primitive);
+
+                // Bound from the mesh's and the section's first elements, since downlevel targets can't offset draws
+                // by a base vertex or a first instance.
+                let offset = section.start as BufferAddress * size_of::<T::Instance>() as BufferAddress;
+                pass.set_vertex_buffer(0, meshes.vertices.slice(mesh_vertices));
+                pass.set_vertex_buffer(1, instances.slice(offset..));
+                pass.set_index_buffer(meshes.indices.slice(..), 0, IndexFormat::Uint32);
+                pass.draw_indexed(mesh_indices, 0, 0..section.end - section.start);
+            }
+        }
 
         RenderCommandResult::Success
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::{prelude::*, render::render_resource::IndexFormat};
+
+    use crate::{
+        draw::vertex::{DrawKey, DrawVertex},
+        shape::{
+            pipeline::{BatchLimits, BatchReorder, Queued},
+            vertex::{Request, Topology},
+        },
+    };
+
+    /// A unit quad at `x`, identified by it in [`ids`].
+    fn quad(layer: f32, x: f32, key: DrawKey) -> Queued<DrawVertex> {
+        Queued::Frame(Request {
+            layer,
+            topology: Topology::TriangleList,
+            vertices: vec![
+                DrawVertex::new(x, 0.0, Color::WHITE),
+                DrawVertex::new(x + 1.0, 0.0, Color::WHITE),
+                DrawVertex::new(x, 1.0, Color::WHITE),
+            ],
+            indices: vec![0, 1, 2],
+            key,
+        })
+    }
+
+    fn ids(queued: &[Queued<DrawVertex>]) -> Vec<f32> {
+        queued.iter().map(|request| request.bounds().unwrap().min.x).collect()
+    }
+
+    fn reorder(mode: BatchReorder, mut queued: Vec<Queued<DrawVertex>>) -> Vec<Queued<DrawVertex>> {
+        mode.apply(&mut queued, &mut Vec::new(), &mut Vec::new());
+        queued
+    }
+
+    #[test]
+    fn chunks_split_at_max_vertices() {
+        let limits = BatchLimits {
+            max_vertices: 100,
+            u16_indices: true,
+        };
+
+        assert_eq!(limits.chunk(0, 60, 40).0, 0);
+        assert_eq!(limits.chunk(0, 60, 41).0, 60);
+        assert_eq!(limits.chunk(60, 150, 10).0, 60);
+
+        // Requests past the limit on their own only leave non-empty chunks.
+        assert_eq!(limits.chunk(60, 60, 500).0, 60);
+        assert_eq!(limits.chunk(60, 70, 500).0, 70);
+    }
+
+    #[test]
+    fn chunks_use_u16_indices_while_they_fit() {
+        let limits = BatchLimits {
+            max_vertices: u32::MAX,
+            u16_indices: true,
+        };
+
+        let max = u16::MAX as u32;
+        assert_eq!(limits.chunk(0, 0, max), (0, IndexFormat::Uint16));
+        assert_eq!(limits.chunk(0, 0, max + 1), (0, IndexFormat::Uint32));
+        assert_eq!(limits.chunk(0, 1, max), (0, IndexFormat::Uint32));
+        assert_eq!(limits.chunk(1, 1, max), (1, IndexFormat::Uint16));
+
+        let limits = BatchLimits {
+            u16_indices: false,
+            ..limits
+        };
+        assert_eq!(limits.chunk(0, 0, 3), (0, IndexFormat::Uint32));
+    }
+
+    #[test]
+    fn reorder_groups_equal_keys_within_a_layer() {
+        let (a, b) = (DrawKey::default(), DrawKey::additive());
+        for mode in [BatchReorder::NonOverlapping, BatchReorder::EqualLayers] {
+            let queued = reorder(mode, vec![quad(0.0, 0.0, a), quad(0.0, 2.0, b), quad(0.0, 4.0, a)]);
+            assert_eq!(ids(&queued), [0.0, 4.0, 2.0]);
+        }
+    }
+
+    #[test]
+    fn reorder_stops_at_overlapping_requests() {
+        let (a, b) = (DrawKey::default(), DrawKey::additive());
+        let queued = vec![quad(0.0, 0.0, a), quad(0.0, 2.0, b), quad(0.0, 2.5, a)];
+        assert_eq!(ids(&reorder(BatchReorder::NonOverlapping, queued)), [0.0, 2.0, 2.5]);
+
+        let queued = vec![quad(0.0, 0.0, a), quad(0.0, 2.0, b), quad(0.0, 2.5, a)];
+        assert_eq!(ids(&reorder(BatchReorder::EqualLayers, queued)), [0.0, 2.5, 2.0]);
+    }
+
+    #[test]
+    fn reorder_crosses_layers_only_without_overlap() {
+        let (a, b) = (DrawKey::default(), DrawKey::additive());
+        let queued = reorder(BatchReorder::NonOverlapping, vec![
+            quad(0.0, 0.0, a),
+            quad(1.0, 2.0, b),
+            quad(2.0, 4.0, a),
+        ]);
+        assert_eq!(ids(&queued), [0.0, 4.0, 2.0]);
+
+        // The moved request is drawn at the layer of the one it passed, keeping the drawn layers sorted.
+        let mut layers = Vec::new();
+        BatchReorder::drawn_layers(&queued, &mut layers);
+        assert_eq!(layers, [0.0, 1.0, 1.0]);
+
+        let queued = vec![quad(0.0, 0.0, a), quad(1.0, 2.0, b), quad(2.0, 4.0, a)];
+        assert_eq!(ids(&reorder(BatchReorder::EqualLayers, queued)), [0.0, 2.0, 4.0]);
+    }
+
+    #[test]
+    fn disabled_reorder_keeps_the_order() {
+        let (a, b) = (DrawKey::default(), DrawKey::additive());
+        let queued = vec![quad(0.0, 0.0, a), quad(0.0, 2.0, b), quad(0.0, 4.0, a)];
+        assert_eq!(ids(&reorder(BatchReorder::Disabled, queued)), [0.0, 2.0, 4.0]);
+    }
+
+    #[test]
+    fn reorder_looks_back_at_most_a_window() {
+        let (a, b) = (DrawKey::default(), DrawKey::additive());
+        let queued = |between: usize| {
+            let mut queued = vec![quad(0.0, 0.0, a)];
+            queued.extend((1..=between).map(|i| quad(0.0, 2.0 * i as f32, b)));
+            queued.push(quad(0.0, 2.0 * (between + 1) as f32, a));
+            queued
+        };
+
+        let within = BatchReorder::WINDOW - 1;
+        assert_eq!(
+            ids(&reorder(BatchReorder::NonOverlapping, queued(within)))[1],
+            2.0 * (within + 1) as f32
+        );
+
+        let past = BatchReorder::WINDOW;
+        assert_eq!(ids(&reorder(BatchReorder::NonOverlapping, queued(past)))[1], 2.0);
+    }
+
+    #[test]
+    fn reorder_keeps_every_request_once() {
+        let keys = [DrawKey::default(), DrawKey::additive()];
+        for mode in [BatchReorder::NonOverlapping, BatchReorder::EqualLayers] {
+            // Every third request overlaps the one before it.
+            let queued = (0..200)
+                .map(|i| {
+                    let x = 2.0 * i as f32 - if i % 3 == 0 { 1.5 } else { 0.0 };
+                    quad((i / 7) as f32, x, keys[i * i % 5 % 2])
+                })
+                .collect::<Vec<_>>();
+
+            let mut expected = ids(&queued);
+            let mut actual = ids(&reorder(mode, queued));
+            assert_ne!(actual, expected);
+
+            expected.sort_by(f32::total_cmp);
+            actual.sort_by(f32::total_cmp);
+            assert_eq!(actual, expected);
+        }
+    }
+}
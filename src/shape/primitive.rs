@@ -0,0 +1,162 @@
+use std::ops::Range;
+
+use bevy::{
+    core::{Pod, Zeroable},
+    prelude::*,
+    render::{
+        render_resource::{
+            Buffer, BufferAddress, BufferInitDescriptor, BufferUsages, VertexAttribute, VertexBufferLayout, VertexFormat,
+            VertexStepMode,
+        },
+        renderer::RenderDevice,
+    },
+};
+
+use crate::util::{math::vec_angle, FloatExt};
+
+/// Unit meshes that instanced requests are drawn with. Each of their vertices carries a corner index, which shaders use
+/// to pick per-instance attributes such as colors.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Primitive {
+    /// Spans `(0, -0.5)` to `(1, 0.5)`; corners go left-start, right-start, right-end, left-end.
+    Quad,
+    /// Base from `(0, 0.5)` to `(0, -0.5)` and tip at `(1, 0)`; corners go left, right, tip.
+    Tri,
+    /// Unit radius around the origin; the center is corner 0 and the rim is corner 1.
+    Circle,
+}
+
+impl Primitive {
+    pub const CIRCLE_SEGMENTS: u32 = 32;
+
+    #[inline]
+    const fn index(self) -> usize {
+        match self {
+            Self::Quad => 0,
+            Self::Tri => 1,
+            Self::Circle => 2,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone)]
+pub struct MeshVertex {
+    pub position: [f32; 2],
+    pub corner: u32,
+}
+
+impl MeshVertex {
+    /// Instance attributes of instanced vertex types must start past these locations.
+    pub const LAYOUT: &'static [VertexAttribute] = &[
+        VertexAttribute {
+            format: VertexFormat::Float32x2,
+            offset: 0,
+            shader_location: 0,
+        },
+        VertexAttribute {
+            format: VertexFormat::Uint32,
+            offset: size_of::<[f32; 2]>() as BufferAddress,
+            shader_location: 1,
+        },
+    ];
+
+    #[inline]
+    pub const fn new(x: f32, y: f32, corner: u32) -> Self {
+        Self {
+            position: [x, y],
+            corner,
+        }
+    }
+
+    #[inline]
+    pub fn buffer_layout() -> VertexBufferLayout {
+        VertexBufferLayout {
+            array_stride: size_of::<Self>() as BufferAddress,
+            step_mode: VertexStepMode::Vertex,
+            attributes: Self::LAYOUT.into(),
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct PrimitiveMeshes {
+    pub vertices: Buffer,
+    pub indices: Buffer,
+    ranges: [(Range<BufferAddress>, Range<u32>); 3],
+}
+
+impl PrimitiveMeshes {
+    /// Returns the byte range of `primitive`'s vertices and its index range in the mesh buffers. Indices are relative
+    /// to the mesh's first vertex, so binding its vertices alone draws it without a base vertex, which downlevel
+    /// targets such as WebGL2 lack.
+    #[inline]
+    pub fn range(&self, primitive: Primitive) -> (Range<BufferAddress>, Range<u32>) {
+        self.ranges[primitive.index()].clone()
+    }
+}
+
+impl FromWorld for PrimitiveMeshes {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut add = |mesh_vertices: &[MeshVertex], mesh_indices: &[u32]| {
+            let stride = size_of::<MeshVertex>() as BufferAddress;
+            let base = vertices.len() as BufferAddress * stride;
+            let start = indices.len() as u32;
+
+            vertices.extend_from_slice(mesh_vertices);
+            indices.extend_from_slice(mesh_indices);
+            (base..vertices.len() as BufferAddress * stride, start..indices.len() as u32)
+        };
+
+        let quad = add(
+            &[
+                MeshVertex::new(0.0, 0.5, 0),
+                MeshVertex::new(0.0, -0.5, 1),
+                MeshVertex::new(1.0, -0.5, 2),
+                MeshVertex::new(1.0, 0.5, 3),
+            ],
+            &[0, 1, 2, 2, 3, 0],
+        );
+
+        let tri = add(
+            &[
+                MeshVertex::new(0.0, 0.5, 0),
+                MeshVertex::new(0.0, -0.5, 1),
+                MeshVertex::new(1.0, 0.0, 2),
+            ],
+            &[0, 1, 2],
+        );
+
+        let segments = Primitive::CIRCLE_SEGMENTS;
+        let circle = add(
+            &[MeshVertex::new(0.0, 0.0, 0)]
+                .into_iter()
+                .chain((0..segments).map(|i| {
+                    let Vec2 { x, y } = vec_angle(i as f32 / segments as f32 * f32::PI2, 1.0, 0.0);
+                    MeshVertex::new(x, y, 1)
+                }))
+                .collect::<Vec<_>>(),
+            &(0..segments)
+                .flat_map(|i| [0, 1 + i, 1 + (i + 1) % segments])
+                .collect::<Vec<_>>(),
+        );
+
+        Self {
+            vertices: device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("primitive_vertex_buffer"),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: BufferUsages::VERTEX,
+            }),
+            indices: device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("primitive_index_buffer"),
+                contents: bytemuck::cast_slice(&indices),
+                usage: BufferUsages::INDEX,
+            }),
+            ranges: [quad, tri, circle],
+        }
+    }
+}
@@ -0,0 +1,389 @@
+use std::{mem, ops::Range};
+
+use bevy::{
+    core::Pod,
+    ecs::entity::EntityHashMap,
+    prelude::*,
+    render::{
+        render_resource::{Buffer, BufferAddress, BufferDescriptor, BufferUsages},
+        renderer::{RenderDevice, RenderQueue},
+    },
+};
+
+use crate::shape::{
+    buffer::{Capacity, ShrinkPolicy},
+    primitive::Primitive,
+    vertex::{InstanceRequest, Request, Topology, Vertex},
+};
+
+/// Marks an extracted shaper as retained; its requests are cached by `entity` and reused in later frames until
+/// `changed` is set, e.g. from change detection on the main-world components it was extracted from.
+#[derive(Component, Copy, Clone)]
+pub struct Retained {
+    pub entity: Entity,
+    pub changed: bool,
+}
+
+impl Retained {
+    #[inline]
+    pub const fn new(entity: Entity, changed: bool) -> Self {
+        Self { entity, changed }
+    }
+}
+
+/// A GPU buffer whose contents are addressed by persistent regions. Only regions written to since the last upload are
+/// re-sent to the GPU, unless the buffer has to grow.
+pub struct RegionBuffer<T: Pod> {
+    label: &'static str,
+    usage: BufferUsages,
+    values: Vec<T>,
+    free: Vec<Range<u32>>,
+    dirty: Vec<Range<u32>>,
+    buffer: Option<Buffer>,
+    capacity: Capacity,
+}
+
+impl<T: Pod> RegionBuffer<T> {
+    #[inline]
+    pub fn new(label: &'static str, usage: BufferUsages) -> Self {
+        Self {
+            label,
+            usage,
+            values: Vec::new(),
+            free: Vec::new(),
+            dirty: Vec::new(),
+            buffer: None,
+            capacity: default(),
+        }
+    }
+
+    #[inline]
+    pub fn buffer(&self) -> Option<&Buffer> {
+        self.buffer.as_ref()
+    }
+
+    #[inline]
+    pub fn values(&self) -> &[T] {
+        &self.values
+    }
+
+    pub fn alloc(&mut self, len: u32) -> Range<u32> {
+        if len == 0 {
+            return 0..0
+        }
+
+        if let Some(i) = self.free.iter().position(|range| range.len() as u32 >= len) {
+            let range = &mut self.free[i];
+            let start = range.start;
+
+            range.start += len;
+            if range.start == range.end {
+                self.free.remove(i);
+            }
+
+            return start..start + len
+        }
+
+        let start = self.values.len() as u32;
+        self.values.resize(self.values.len() + len as usize, T::zeroed());
+        start..start + len
+    }
+
+    pub fn free(&mut self, range: Range<u32>) {
+        if range.is_empty() {
+            return
+        }
+
+        let i = self.free.partition_point(|free| free.start < range.start);
+        self.free.insert(i, range);
+
+        // Coalesce neighboring free regions so large allocations can reuse them.
+        let mut merged = Vec::<Range<u32>>::with_capacity(self.free.len());
+        for range in self.free.drain(..) {
+            match merged.last_mut() {
+                Some(prev) if prev.end == range.start => prev.end = range.end,
+                _ => merged.push(range),
+            }
+        }
+        self.free = merged;
+
+        if let Some(last) = self.free.last() {
+            if last.end as usize == self.values.len() {
+                self.values.truncate(last.start as usize);
+                self.free.pop();
+            }
+        }
+    }
+
+    pub fn write(&mut self, offset: u32, data: &[T]) {
+        let range = offset..offset + data.len() as u32;
+        self.values[range.start as usize..range.end as usize].copy_from_slice(data);
+        self.dirty.push(range);
+    }
+
+    /// Returns the number of bytes uploaded.
+    pub fn write_buffer(&mut self, device: &RenderDevice, queue: &RenderQueue, policy: &ShrinkPolicy) -> u64 {
+        if self.capacity.reserve(self.values.len(), policy) {
+            self.buffer = None;
+            self.values.shrink_to(self.capacity.get());
+        }
+
+        if self.values.is_empty() || size_of::<T>() == 0 {
+            self.dirty.clear();
+            return 0
+        }
+
+        if self.buffer.is_none() {
+            self.buffer = Some(device.create_buffer(&BufferDescriptor {
+                label: Some(self.label),
+                size: (self.capacity.get() * size_of::<T>()) as BufferAddress,
+                usage: BufferUsages::COPY_DST | self.usage,
+                mapped_at_creation: false,
+            }));
+
+            self.dirty.clear();
+            self.dirty.push(0..self.values.len() as u32);
+        }
+
+        let Some(buffer) = &self.buffer else { return 0 };
+        let mut bytes = 0;
+        for range in self.dirty.drain(..) {
+            let (start, end) = (range.start as usize, (range.end as usize).min(self.values.len()));
+            if start < end {
+                queue.write_buffer(
+                    buffer,
+                    (start * size_of::<T>()) as BufferAddress,
+                    bytemuck::cast_slice(&self.values[start..end]),
+                );
+                bytes += ((end - start) * size_of::<T>()) as u64;
+            }
+        }
+
+        bytes
+    }
+}
+
+/// A request whose geometry lives in [`RetainedBatch`], referenced by its absolute index range, or instance range if
+/// `primitive` is set.
+pub struct RetainedRequest<K> {
+    pub layer: f32,
+    pub topology: Topology,
+    pub primitive: Option<Primitive>,
+    pub range: Range<u32>,
+    /// See [`Vertex::bounds`].
+    pub bounds: Option<Rect>,
+    pub key: K,
+}
+
+impl<K: Clone> Clone for RetainedRequest<K> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            layer: self.layer,
+            topology: self.topology,
+            primitive: self.primitive,
+            range: self.range.clone(),
+            bounds: self.bounds,
+            key: self.key.clone(),
+        }
+    }
+}
+
+struct RetainedEntry<K> {
+    vertices: Range<u32>,
+    indices: Range<u32>,
+    instances: Range<u32>,
+    requests: Vec<RetainedRequest<K>>,
+    touched: bool,
+}
+
+#[derive(Resource)]
+pub struct RetainedBatch<T: Vertex> {
+    pub vertices: RegionBuffer<T>,
+    pub indices: RegionBuffer<u32>,
+    pub instances: RegionBuffer<T::Instance>,
+    entries: EntityHashMap<RetainedEntry<T::Key>>,
+}
+
+impl<T: Vertex> Default for RetainedBatch<T> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            vertices: RegionBuffer::new("retained_vertex_buffer", BufferUsages::VERTEX),
+            indices: RegionBuffer::new("retained_index_buffer", BufferUsages::INDEX),
+            instances: RegionBuffer::new("retained_instance_buffer", BufferUsages::VERTEX),
+            entries: default(),
+        }
+    }
+}
+
+impl<T: Vertex> RetainedBatch<T> {
+    /// Returns the cached requests of `entity` if there are any, keeping them alive for this frame.
+    pub fn reuse(&mut self, entity: Entity) -> Option<&[RetainedRequest<T::Key>]> {
+        let entry = self.entries.get_mut(&entity)?;
+        entry.touched = true;
+        Some(&entry.requests)
+    }
+
+    /// Replaces the cached requests of `entity`, moving their geometry into the persistent buffers.
+    pub fn store(
+        &mut self,
+        entity: Entity,
+        requests: impl IntoIterator<Item = Request<T>>,
+        instances: impl IntoIterator<Item = InstanceRequest<T>>,
+    ) -> &[RetainedRequest<T::Key>] {
+        fn realloc<T: Pod>(buffer: &mut RegionBuffer<T>, range: &mut Range<u32>, len: u32) {
+            if range.len() as u32 != len {
+                buffer.free(mem::replace(range, 0..0));
+                *range = buffer.alloc(len);
+            }
+        }
+
+        let requests = requests.into_iter().collect::<Vec<_>>();
+        let instances = instances.into_iter().collect::<Vec<_>>();
+        let vertex_count = requests.iter().map(|req| req.vertices.len() as u32).sum();
+        let index_count = requests
+            .iter()
+            .map(|req| req.topology.index_count(req.indices.len()) as u32)
+            .sum();
+
+        let mut entry = self.entries.remove(&entity).unwrap_or_else(|| RetainedEntry {
+            vertices: 0..0,
+            indices: 0..0,
+            instances: 0..0,
+            requests: Vec::with_capacity(requests.len() + instances.len()),
+            touched: false,
+        });
+
+        realloc(&mut self.vertices, &mut entry.vertices, vertex_count);
+        realloc(&mut self.indices, &mut entry.indices, index_count);
+        realloc(&mut self.instances, &mut entry.instances, instances.len() as u32);
+        entry.requests.clear();
+
+        let (mut vertex_offset, mut index_offset) = (entry.vertices.start, entry.indices.start);
+        for Request {
+            layer,
+            topology,
+            vertices,
+            indices,
+            key,
+        } in requests
+        {
+            let bounds = T::bounds(&vertices);
+            let indices = topology.rebase(indices, vertex_offset).collect::<Vec<_>>();
+            self.vertices.write(vertex_offset, &vertices);
+            self.indices.write(index_offset, &indices);
+
+            entry.requests.push(RetainedRequest {
+                layer,
+                topology,
+                primitive: None,
+                range: index_offset..index_offset + indices.len() as u32,
+                bounds,
+                key,
+            });
+
+            vertex_offset += vertices.len() as u32;
+            index_offset += indices.len() as u32;
+        }
+
+        for (
+            offset,
+            InstanceRequest {
+                layer,
+                primitive,
+                instance,
+                key,
+            },
+        ) in entry.instances.clone().zip(instances)
+        {
+            self.instances.write(offset, &[instance]);
+            entry.requests.push(RetainedRequest {
+                layer,
+                topology: Topology::TriangleList,
+                primitive: Some(primitive),
+                range: offset..offset + 1,
+                bounds: T::instance_bounds(&instance, primitive),
+                key,
+            });
+        }
+
+        entry.touched = true;
+        self.entries.insert(entity, entry);
+        &self.entries[&entity].requests
+    }
+
+    /// Frees the geometry of every entity that wasn't drawn this frame.
+    pub fn evict(&mut self) {
+        let Self {
+            ref mut vertices,
+            ref mut indices,
+            ref mut instances,
+            ref mut entries,
+        } = *self;
+
+        entries.retain(|_, entry| {
+            let touched = mem::replace(&mut entry.touched, false);
+            if !touched {
+                vertices.free(entry.vertices.clone());
+                indices.free(entry.indices.clone());
+                instances.free(entry.instances.clone());
+            }
+
+            touched
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::render::render_resource::BufferUsages;
+
+    use crate::shape::retain::RegionBuffer;
+
+    fn buffer() -> RegionBuffer<u32> {
+        RegionBuffer::new("test_buffer", BufferUsages::VERTEX)
+    }
+
+    #[test]
+    fn freed_regions_are_reused() {
+        let mut buffer = buffer();
+        let a = buffer.alloc(4);
+        let b = buffer.alloc(8);
+        let _c = buffer.alloc(2);
+        assert_eq!((a.clone(), b.clone()), (0..4, 4..12));
+
+        buffer.free(b);
+        assert_eq!(buffer.alloc(3), 4..7);
+        assert_eq!(buffer.alloc(5), 7..12);
+        assert_eq!(buffer.alloc(1), 14..15);
+        assert_eq!(buffer.values().len(), 15);
+    }
+
+    #[test]
+    fn neighboring_regions_merge() {
+        let mut buffer = buffer();
+        let regions = [2, 3, 4, 1].map(|len| buffer.alloc(len));
+
+        // Freed out of order, the first three only fit a larger allocation once merged.
+        buffer.free(regions[2].clone());
+        buffer.free(regions[0].clone());
+        buffer.free(regions[1].clone());
+        assert_eq!(buffer.alloc(9), 0..9);
+        assert_eq!(buffer.values().len(), 10);
+    }
+
+    #[test]
+    fn trailing_regions_are_truncated() {
+        let mut buffer = buffer();
+        let a = buffer.alloc(4);
+        let b = buffer.alloc(4);
+
+        buffer.free(b);
+        assert_eq!(buffer.values().len(), 4);
+
+        buffer.free(a);
+        assert!(buffer.values().is_empty());
+        assert_eq!(buffer.alloc(2), 0..2);
+    }
+}
@@ -0,0 +1,358 @@
+use std::sync::Mutex;
+
+use bevy::{
+    core::{Pod, Zeroable},
+    prelude::*,
+    render::{
+        render_resource::{
+            BlendState, BufferAddress, ColorWrites, RenderPipelineDescriptor, ShaderDefVal, VertexAttribute, VertexFormat,
+        },
+        Render, RenderApp,
+    },
+};
+
+use crate::{
+    shape::{
+        diagnostic::ShapeErrors,
+        pipeline::{Requests, ShapeShader},
+        retain::RetainedBatch,
+        uniform::{BatchUniform, UniformParams},
+        vertex::{DrawSpace, Request, Topology, Vertex, VertexKey},
+        ShapePlugin, ShapeSystems,
+    },
+    util::TrackLoading,
+};
+
+/// Renders [`SdfVertex`] shapes drawn through [`Shapes::sdf`] by shapers of any vertex type. Without it, they're
+/// dropped with a [`ShapeError::MissingSdfPlugin`].
+///
+/// [`Shapes::sdf`]: crate::shape::vertex::Shapes::sdf
+/// [`ShapeError::MissingSdfPlugin`]: crate::shape::diagnostic::ShapeError::MissingSdfPlugin
+#[derive(Default)]
+pub struct SdfPlugin {
+    track_loading: TrackLoading,
+}
+
+impl SdfPlugin {
+    /// Adds the shader to [`AssetsLoading`](iyes_progress::prelude::AssetsLoading) when entering `state`, so an
+    /// [`iyes_progress`] loading state waits for it.
+    #[inline]
+    pub fn track_loading(mut self, state: impl States) -> Self {
+        self.track_loading = TrackLoading::default().shader(state, |shader: &ShapeShader<SdfVertex>| &shader.handle);
+        self
+    }
+}
+
+impl Plugin for SdfPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(ShapePlugin::<SdfVertex>::default());
+        self.track_loading.apply(app);
+
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .init_resource::<SdfQueue>()
+                .add_systems(Render, queue_sdf.in_set(ShapeSystems::QueueSdf));
+        }
+    }
+}
+
+/// Vertex of shapes rendered analytically from their signed distance in the fragment shader, anti-aliased at any zoom
+/// with four vertices per shape. Every shape is a rounded box, optionally hollowed out into an outline, which covers
+/// circles, rings, rounded boxes and capsules.
+///
+/// Added by [`SdfPlugin`], and drawn through [`Shapes::sdf`] by shapers of any vertex type.
+///
+/// [`Shapes::sdf`]: crate::shape::vertex::Shapes::sdf
+#[repr(C)]
+#[derive(Pod, Zeroable, Copy, Clone)]
+pub struct SdfVertex {
+    pub center: [f32; 2],
+    /// Cosine and sine of the shape's rotation.
+    pub rotation: [f32; 2],
+    /// Corner of the shape's bounding box, relative to `center` before rotating.
+    pub local: [f32; 2],
+    /// Half extents of the box, its corner radius, and the thickness of its outline or `0.0` if filled.
+    pub shape: [f32; 4],
+    /// Linear color at the center of the shape.
+    pub inner: [f32; 4],
+    /// Color at its edge.
+    pub outer: [f32; 4],
+    /// Multiplier of the interpolated color's RGB in HDR views, see
+    /// [`DrawVertex::emissive`](crate::draw::vertex::DrawVertex::emissive).
+    pub emissive: f32,
+}
+
+impl Vertex for SdfVertex {
+    type Key = SdfKey;
+    type Instance = ();
+
+    const SHADER_SOURCE: &'static str = "shaders/sdf.wgsl";
+
+    const LAYOUT: &'static [VertexAttribute] = &[
+        VertexAttribute {
+            format: VertexFormat::Float32x2,
+            offset: 0,
+            shader_location: 0,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x2,
+            offset: size_of::<[f32; 2]>() as BufferAddress,
+            shader_location: 1,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x2,
+            offset: size_of::<[[f32; 2]; 2]>() as BufferAddress,
+            shader_location: 2,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x4,
+            offset: size_of::<[[f32; 2]; 3]>() as BufferAddress,
+            shader_location: 3,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x4,
+            offset: (size_of::<[[f32; 2]; 3]>() + size_of::<[f32; 4]>()) as BufferAddress,
+            shader_location: 4,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x4,
+            offset: (size_of::<[[f32; 2]; 3]>() + size_of::<[[f32; 4]; 2]>()) as BufferAddress,
+            shader_location: 5,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32,
+            offset: (size_of::<[[f32; 2]; 3]>() + size_of::<[[f32; 4]; 3]>()) as BufferAddress,
+            shader_location: 6,
+        },
+    ];
+
+    /// The anti-aliased fringe reaching a pixel past the box isn't accounted for.
+    #[inline]
+    fn position(&self) -> Option<Vec2> {
+        let [cos, sin] = self.rotation;
+        let [x, y] = self.local;
+        Some(Vec2::from_array(self.center) + Vec2::new(x * cos - y * sin, x * sin + y * cos))
+    }
+}
+
+/// A rounded box, see [`SdfVertex`].
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SdfShape {
+    pub center: Vec2,
+    /// In radians.
+    pub angle: f32,
+    pub half_size: Vec2,
+    /// Clamped to the smaller half extent.
+    pub radius: f32,
+    /// Thickness of the outline drawn inside the shape's edge; `0.0` fills it.
+    pub thickness: f32,
+}
+
+impl SdfShape {
+    #[inline]
+    pub fn circle(center: Vec2, radius: f32) -> Self {
+        Self::rounded_box(center, 0.0, Vec2::splat(radius), radius)
+    }
+
+    /// A circle outline reaching `thickness` inwards from `radius`.
+    #[inline]
+    pub fn ring(center: Vec2, radius: f32, thickness: f32) -> Self {
+        Self {
+            thickness,
+            ..Self::circle(center, radius)
+        }
+    }
+
+    #[inline]
+    pub fn rounded_box(center: Vec2, angle: f32, half_size: Vec2, radius: f32) -> Self {
+        Self {
+            center,
+            angle,
+            half_size,
+            radius: radius.min(half_size.x).min(half_size.y).max(0.0),
+            thickness: 0.0,
+        }
+    }
+
+    /// A line from `from` to `to` with round caps, `radius` away from it.
+    #[inline]
+    pub fn capsule(from: Vec2, to: Vec2, radius: f32) -> Self {
+        let delta = to - from;
+        Self::rounded_box(
+            (from + to) / 2.0,
+            delta.y.atan2(delta.x),
+            Vec2::new(delta.length() / 2.0 + radius, radius),
+            radius,
+        )
+    }
+
+    #[inline]
+    pub fn outline(mut self, thickness: f32) -> Self {
+        self.thickness = thickness;
+        self
+    }
+
+    /// The four vertices of the shape, shaded from `inner` at its center to `outer` at its edge, or across the
+    /// thickness of its outline.
+    pub fn vertices(self, inner: Color, outer: Color) -> [SdfVertex; 4] {
+        let Vec2 { x: hw, y: hh } = self.half_size;
+        let (sin, cos) = self.angle.sin_cos();
+        let (inner, outer) = (inner.as_linear_rgba_f32(), outer.as_linear_rgba_f32());
+
+        [[-hw, -hh], [hw, -hh], [hw, hh], [-hw, hh]].map(|local| SdfVertex {
+            center: self.center.to_array(),
+            rotation: [cos, sin],
+            local,
+            shape: [hw, hh, self.radius, self.thickness],
+            inner,
+            outer,
+            emissive: 1.0,
+        })
+    }
+
+    #[inline]
+    pub fn request(self, key: SdfKey, layer: f32, inner: Color, outer: Color) -> Request<SdfVertex> {
+        Request {
+            layer,
+            topology: Topology::TriangleList,
+            vertices: self.vertices(inner, outer).into(),
+            indices: vec![0, 1, 2, 2, 3, 0],
+            key,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+pub struct SdfKey {
+    pub mask: ColorWrites,
+    pub blend: Option<BlendState>,
+    /// Only the tint is used.
+    pub params: UniformParams,
+    /// See [`VertexKey::opaque`]; fragments less than half covered are discarded instead of blended.
+    pub opaque: bool,
+    pub space: DrawSpace,
+}
+
+impl Default for SdfKey {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            mask: ColorWrites::ALL,
+            blend: Some(BlendState::ALPHA_BLENDING),
+            params: default(),
+            opaque: false,
+            space: DrawSpace::World,
+        }
+    }
+}
+
+impl VertexKey for SdfKey {
+    fn specialize(self, desc: &mut RenderPipelineDescriptor) {
+        for target in desc
+            .fragment
+            .iter_mut()
+            .flat_map(|fragment| fragment.targets.iter_mut().flatten())
+        {
+            target.write_mask = self.mask;
+            target.blend = self.blend;
+        }
+    }
+
+    #[inline]
+    fn shader_defs(&self) -> Vec<ShaderDefVal> {
+        self.space.shader_def().into_iter().map(Into::into).collect()
+    }
+
+    #[inline]
+    fn uniform(&self) -> BatchUniform {
+        self.params.uniform()
+    }
+
+    #[inline]
+    fn opaque(&self) -> bool {
+        self.opaque
+    }
+
+    #[inline]
+    fn world_space(&self) -> bool {
+        self.space == DrawSpace::World
+    }
+
+    #[inline]
+    fn pipeline_key(&self) -> Self {
+        Self {
+            params: UniformParams::DEFAULT,
+            ..*self
+        }
+    }
+}
+
+/// SDF shapes of a shaper, along with the retained entity they're cached for.
+type DrawnSdf = (Option<Entity>, Vec<Request<SdfVertex>>);
+
+/// SDF shapes drawn by shapers of every vertex type, handed over to [`queue_sdf`] so that only it accesses
+/// [`SdfVertex`]'s requests and retained geometry.
+#[derive(Resource, Default)]
+pub struct SdfQueue {
+    /// Shapes drawn this frame.
+    pub drawn: Mutex<Vec<DrawnSdf>>,
+    /// Unchanged retained shapers, whose cached shapes are drawn again.
+    pub reused: Mutex<Vec<Entity>>,
+}
+
+pub fn queue_sdf(
+    mut queue: ResMut<SdfQueue>,
+    requests: Res<Requests<SdfVertex>>,
+    mut retained_batch: ResMut<RetainedBatch<SdfVertex>>,
+    errors: Res<ShapeErrors>,
+) {
+    let SdfQueue { drawn, reused } = &mut *queue;
+    let mut values = errors.lock(&requests.values, "requests");
+    let mut retained = errors.lock(&requests.retained, "retained_requests");
+
+    for entity in errors.get_mut(reused, "sdf_reused").drain(..) {
+        if let Some(cached) = retained_batch.reuse(entity) {
+            retained.extend_from_slice(cached);
+        }
+    }
+
+    for (entity, mut shapes) in errors.get_mut(drawn, "sdf_drawn").drain(..) {
+        match entity {
+            Some(entity) => retained.extend_from_slice(retained_batch.store(entity, shapes, [])),
+            None => values.append(&mut shapes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use crate::{shape::sdf::SdfShape, util::math::within};
+
+    #[test]
+    fn rounded_boxes_clamp_their_radius() {
+        let shape = SdfShape::rounded_box(Vec2::ZERO, 0.0, Vec2::new(4.0, 2.0), 3.0);
+        assert_eq!(shape.radius, 2.0);
+
+        let shape = SdfShape::rounded_box(Vec2::ZERO, 0.0, Vec2::new(4.0, 2.0), -1.0);
+        assert_eq!(shape.radius, 0.0);
+        assert_eq!(shape.thickness, 0.0);
+    }
+
+    #[test]
+    fn capsules_span_their_endpoints() {
+        let shape = SdfShape::capsule(Vec2::new(1.0, 1.0), Vec2::new(1.0, 7.0), 2.0);
+        assert_eq!(shape.center, Vec2::new(1.0, 4.0));
+        assert_eq!(shape.half_size, Vec2::new(5.0, 2.0));
+        assert_eq!(shape.radius, 2.0);
+        assert!(within(shape.angle, 90f32.to_radians(), 1e-6));
+    }
+
+    #[test]
+    fn rings_are_outlined_circles() {
+        let shape = SdfShape::ring(Vec2::new(3.0, -2.0), 5.0, 1.5);
+        assert_eq!(shape, SdfShape::circle(Vec2::new(3.0, -2.0), 5.0).outline(1.5));
+        assert_eq!((shape.half_size, shape.radius, shape.thickness), (Vec2::splat(5.0), 5.0, 1.5));
+    }
+}
@@ -0,0 +1,479 @@
+use std::ops::Range;
+
+use bevy::{
+    core_pipeline::{
+        core_2d::{
+            graph::{Core2d, Node2d},
+            Camera2d,
+        },
+        fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    },
+    ecs::{entity::EntityHashMap, query::QueryItem},
+    prelude::*,
+    render::{
+        camera::ExtractedCamera,
+        render_graph::{NodeRunError, RenderGraphApp, RenderGraphContext, RenderLabel, ViewNode, ViewNodeRunner},
+        render_phase::{
+            sort_phase_system, CachedRenderPipelinePhaseItem, DrawFunctionId, DrawFunctions, PhaseItem, RenderPhase,
+        },
+        render_resource::{
+            binding_types::{sampler, texture_2d, uniform_buffer},
+            BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, BlendComponent, BlendFactor, BlendOperation,
+            BlendState, CachedRenderPipelineId, ColorTargetState, ColorWrites, DynamicUniformBuffer, Extent3d,
+            FragmentState, LoadOp, MultisampleState, Operations, PipelineCache, PrimitiveState, RenderPassColorAttachment,
+            RenderPassDescriptor, RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages,
+            SpecializedRenderPipeline, SpecializedRenderPipelines, StoreOp, TextureDescriptor, TextureDimension,
+            TextureFormat, TextureSampleType, TextureUsages, TextureView,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        texture::BevyDefault,
+        view::{ExtractedView, ViewTarget},
+        Extract, ExtractSchedule, Render, RenderApp, RenderSet,
+    },
+    utils::{nonmax::NonMaxU32, FloatOrd},
+};
+
+use crate::shape::opaque::ShapeOpaquePass;
+
+/// Leaves fading afterimages of the shapes in `layers` behind, drawn beneath everything transparent of the 2D camera
+/// it's added to; see [`ShapeTrailPlugin`].
+#[derive(Component, Clone, Debug)]
+pub struct Afterimage {
+    /// Seconds it takes afterimages to fade to half their opacity.
+    pub half_life: f32,
+    /// Opacity of afterimages as they're left behind.
+    pub intensity: f32,
+    /// Layers of the shapes leaving afterimages, offset by their [`DrawLayer`](crate::shape::vertex::DrawLayer).
+    pub layers: Range<f32>,
+}
+
+impl Afterimage {
+    #[inline]
+    pub fn new(layers: Range<f32>) -> Self {
+        Self {
+            half_life: 0.08,
+            intensity: 0.5,
+            layers,
+        }
+    }
+}
+
+/// Accumulates shapes on the layers of a camera's [`Afterimage`] into a buffer of its own, which fades every frame;
+/// added by the first [`ShapePlugin`]. Shaders draw the same regardless, so shapers don't track any history.
+///
+/// Each frame, the buffer is composited beneath the camera's transparent pass, then faded, then drawn the frame's
+/// shapes into, so they only show up in it from the next frame on, behind their current selves. Batches never span
+/// layers both in and out of an [`Afterimage`]'s range.
+///
+/// [`ShapePlugin`]: crate::shape::ShapePlugin
+pub struct ShapeTrailPlugin;
+impl Plugin for ShapeTrailPlugin {
+    fn build(&self, app: &mut App) {
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .init_resource::<DrawFunctions<ShapeTrail2d>>()
+                .init_resource::<SpecializedRenderPipelines<AfterimagePipeline>>()
+                .init_resource::<AfterimageTargets>()
+                .init_resource::<AfterimageUniforms>()
+                .add_systems(ExtractSchedule, extract_afterimages)
+                .add_systems(
+                    Render,
+                    (
+                        sort_phase_system::<ShapeTrail2d>.in_set(RenderSet::PhaseSort),
+                        prepare_afterimages.in_set(RenderSet::PrepareResources),
+                    ),
+                )
+                .add_render_graph_node::<ViewNodeRunner<ShapeTrailPassNode>>(Core2d, ShapeTrailPass)
+                .add_render_graph_edges(Core2d, (ShapeOpaquePass, ShapeTrailPass, Node2d::MainPass));
+        }
+    }
+
+    fn finish(&self, app: &mut App) {
+        let shader = AfterimageShader {
+            handle: app.world.resource::<AssetServer>().load(AfterimageShader::SOURCE),
+        };
+        app.insert_resource(shader.clone());
+
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app.insert_resource(shader).init_resource::<AfterimagePipeline>();
+        }
+    }
+}
+
+/// Phase of shape batches drawn into [`Afterimage`] buffers, sorted back-to-front like
+/// [`Transparent2d`](bevy::core_pipeline::core_2d::Transparent2d).
+pub struct ShapeTrail2d {
+    pub sort_key: FloatOrd,
+    pub entity: Entity,
+    pub pipeline: CachedRenderPipelineId,
+    pub draw_function: DrawFunctionId,
+    pub batch_range: Range<u32>,
+    pub dynamic_offset: Option<NonMaxU32>,
+}
+
+impl PhaseItem for ShapeTrail2d {
+    type SortKey = FloatOrd;
+
+    #[inline]
+    fn entity(&self) -> Entity {
+        self.entity
+    }
+
+    #[inline]
+    fn sort_key(&self) -> Self::SortKey {
+        self.sort_key
+    }
+
+    #[inline]
+    fn draw_function(&self) -> DrawFunctionId {
+        self.draw_function
+    }
+
+    #[inline]
+    fn sort(items: &mut [Self]) {
+        radsort::sort_by_key(items, |item| item.sort_key.0);
+    }
+
+    #[inline]
+    fn batch_range(&self) -> &Range<u32> {
+        &self.batch_range
+    }
+
+    #[inline]
+    fn batch_range_mut(&mut self) -> &mut Range<u32> {
+        &mut self.batch_range
+    }
+
+    #[inline]
+    fn dynamic_offset(&self) -> Option<NonMaxU32> {
+        self.dynamic_offset
+    }
+
+    #[inline]
+    fn dynamic_offset_mut(&mut self) -> &mut Option<NonMaxU32> {
+        &mut self.dynamic_offset
+    }
+}
+
+impl CachedRenderPipelinePhaseItem for ShapeTrail2d {
+    #[inline]
+    fn cached_pipeline(&self) -> CachedRenderPipelineId {
+        self.pipeline
+    }
+}
+
+/// The [`Afterimage`] of a view.
+#[derive(Component)]
+pub struct ExtractedAfterimage {
+    /// Opacity lost since the last frame.
+    pub fade: f32,
+    pub intensity: f32,
+    pub layers: Range<f32>,
+}
+
+type AfterimageCameras<'w, 's> = Query<'w, 's, (Entity, &'static Camera, &'static Afterimage), With<Camera2d>>;
+
+fn extract_afterimages(mut commands: Commands, time: Extract<Res<Time>>, cameras: Extract<AfterimageCameras>) {
+    for (entity, camera, afterimage) in &cameras {
+        if !camera.is_active {
+            continue
+        }
+
+        let fade = 1.0 - 0.5f32.powf(time.delta_seconds() / afterimage.half_life.max(1e-4));
+        commands
+            .get_or_spawn(entity)
+            .insert((RenderPhase::<ShapeTrail2d>::default(), ExtractedAfterimage {
+                fade,
+                intensity: afterimage.intensity,
+                layers: afterimage.layers.clone(),
+            }));
+    }
+}
+
+/// The strong handle to the afterimage shader, in both the main and the render world.
+#[derive(Resource, Clone)]
+pub struct AfterimageShader {
+    pub handle: Handle<Shader>,
+}
+
+impl AfterimageShader {
+    pub const SOURCE: &'static str = "shaders/afterimage.wgsl";
+}
+
+pub use layout::AfterimageUniform;
+
+// See `shape::uniform` for why this allow sits on a module.
+#[allow(dead_code)]
+mod layout {
+    use bevy::render::render_resource::ShaderType;
+
+    #[derive(Copy, Clone, ShaderType)]
+    pub struct AfterimageUniform {
+        pub intensity: f32,
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct AfterimageUniforms(pub DynamicUniformBuffer<AfterimageUniform>);
+
+/// Accumulation buffers by view, kept across frames as long as their views have an [`Afterimage`] and don't resize.
+#[derive(Resource, Default)]
+pub struct AfterimageTargets(EntityHashMap<AfterimageTarget>);
+
+pub struct AfterimageTarget {
+    pub view: TextureView,
+    pub size: UVec2,
+    pub hdr: bool,
+}
+
+/// What a view's [`ShapeTrailPassNode`] draws with.
+#[derive(Component)]
+pub struct AfterimageView {
+    pub target: TextureView,
+    pub fade: CachedRenderPipelineId,
+    pub composite: CachedRenderPipelineId,
+    pub fade_color: Color,
+    pub offset: u32,
+}
+
+#[derive(Resource)]
+pub struct AfterimagePipeline {
+    shader: Handle<Shader>,
+    layout: BindGroupLayout,
+    sampler: Sampler,
+}
+
+impl FromWorld for AfterimagePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let shader = world.resource::<AfterimageShader>().handle.clone();
+        let device = world.resource::<RenderDevice>();
+
+        Self {
+            shader,
+            layout: device.create_bind_group_layout(
+                "afterimage_layout",
+                &BindGroupLayoutEntries::sequential(
+                    ShaderStages::FRAGMENT,
+                    (
+                        texture_2d(TextureSampleType::Float { filterable: true }),
+                        sampler(SamplerBindingType::Filtering),
+                        uniform_buffer::<AfterimageUniform>(true),
+                    ),
+                ),
+            ),
+            sampler: device.create_sampler(&SamplerDescriptor::default()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+pub enum AfterimageKey {
+    /// Fades the accumulation buffer by the blend constant.
+    Fade { hdr: bool },
+    /// Draws the accumulation buffer, premultiplied, onto the view.
+    Composite { hdr: bool, samples: u32 },
+}
+
+impl SpecializedRenderPipeline for AfterimagePipeline {
+    type Key = AfterimageKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let (hdr, samples, layout, entry_point, blend) = match key {
+            AfterimageKey::Fade { hdr } => {
+                let fade = BlendComponent {
+                    src_factor: BlendFactor::Zero,
+                    dst_factor: BlendFactor::OneMinusConstant,
+                    operation: BlendOperation::Add,
+                };
+                (hdr, 1, Vec::new(), "fade_main", BlendState {
+                    color: fade,
+                    alpha: fade,
+                })
+            }
+            AfterimageKey::Composite { hdr, samples } => (
+                hdr,
+                samples,
+                vec![self.layout.clone()],
+                "composite_main",
+                BlendState::PREMULTIPLIED_ALPHA_BLENDING,
+            ),
+        };
+
+        RenderPipelineDescriptor {
+            label: Some("afterimage_pipeline".into()),
+            layout,
+            push_constant_ranges: Vec::new(),
+            vertex: fullscreen_shader_vertex_state(),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: samples,
+                ..default()
+            },
+            fragment: Some(FragmentState {
+                shader: self.shader.clone(),
+                shader_defs: Vec::new(),
+                entry_point: entry_point.into(),
+                targets: vec![Some(ColorTargetState {
+                    format: match hdr {
+                        true => ViewTarget::TEXTURE_FORMAT_HDR,
+                        false => TextureFormat::bevy_default(),
+                    },
+                    blend: Some(blend),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn prepare_afterimages(
+    mut commands: Commands,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    msaa: Res<Msaa>,
+    pipeline: Res<AfterimagePipeline>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<AfterimagePipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    (mut targets, mut uniforms): (ResMut<AfterimageTargets>, ResMut<AfterimageUniforms>),
+    views: Query<(Entity, &ExtractedCamera, &ExtractedView, &ExtractedAfterimage)>,
+) {
+    targets.0.retain(|&entity, _| views.contains(entity));
+    let Some(mut writer) = uniforms.0.get_writer(views.iter().len(), &device, &queue) else {
+        return
+    };
+
+    for (entity, camera, view, afterimage) in &views {
+        let Some(size) = camera.physical_viewport_size else { continue };
+
+        // New textures start out cleared, so resizing the view drops its afterimages.
+        let target = targets
+            .0
+            .entry(entity)
+            .or_insert_with(|| create_target(&device, size, view.hdr));
+        if target.size != size || target.hdr != view.hdr {
+            *target = create_target(&device, size, view.hdr);
+        }
+
+        let fade = pipelines.specialize(&pipeline_cache, &pipeline, AfterimageKey::Fade { hdr: view.hdr });
+        let composite = pipelines.specialize(&pipeline_cache, &pipeline, AfterimageKey::Composite {
+            hdr: view.hdr,
+            samples: msaa.samples(),
+        });
+
+        commands.entity(entity).insert(AfterimageView {
+            target: target.view.clone(),
+            fade,
+            composite,
+            fade_color: Color::rgba_linear(afterimage.fade, afterimage.fade, afterimage.fade, afterimage.fade),
+            offset: writer.write(&AfterimageUniform {
+                intensity: afterimage.intensity,
+            }),
+        });
+    }
+}
+
+fn create_target(device: &RenderDevice, size: UVec2, hdr: bool) -> AfterimageTarget {
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some("afterimage_texture"),
+        size: Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: match hdr {
+            true => ViewTarget::TEXTURE_FORMAT_HDR,
+            false => TextureFormat::bevy_default(),
+        },
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+
+    AfterimageTarget {
+        view: texture.create_view(&default()),
+        size,
+        hdr,
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+pub struct ShapeTrailPass;
+
+#[derive(Default)]
+pub struct ShapeTrailPassNode;
+impl ViewNode for ShapeTrailPassNode {
+    type ViewQuery = (
+        &'static ExtractedCamera,
+        &'static RenderPhase<ShapeTrail2d>,
+        &'static ViewTarget,
+        &'static AfterimageView,
+    );
+
+    fn run<'w>(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext<'w>,
+        (camera, phase, target, view): QueryItem<'w, Self::ViewQuery>,
+        world: &'w World,
+    ) -> Result<(), NodeRunError> {
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let (Some(fade), Some(composite), Some(uniforms)) = (
+            pipeline_cache.get_render_pipeline(view.fade),
+            pipeline_cache.get_render_pipeline(view.composite),
+            world.resource::<AfterimageUniforms>().0.binding(),
+        ) else {
+            return Ok(())
+        };
+
+        let afterimage_pipeline = world.resource::<AfterimagePipeline>();
+        let bind_group = render_context.render_device().create_bind_group(
+            "afterimage_bind_group",
+            &afterimage_pipeline.layout,
+            &BindGroupEntries::sequential((&view.target, &afterimage_pipeline.sampler, uniforms)),
+        );
+
+        // Clears the target to the camera's clear color if no opaque shapes were drawn before.
+        let mut pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("afterimage_composite_pass"),
+            color_attachments: &[Some(target.get_color_attachment())],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        if let Some(viewport) = &camera.viewport {
+            pass.set_camera_viewport(viewport);
+        }
+
+        pass.set_render_pipeline(composite);
+        pass.set_bind_group(0, &bind_group, &[view.offset]);
+        pass.draw(0..3, 0..1);
+        drop(pass);
+
+        let mut pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("shape_trail_pass_2d"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: &view.target,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        pass.set_render_pipeline(fade);
+        pass.set_blend_constant(view.fade_color);
+        pass.draw(0..3, 0..1);
+
+        phase.render(&mut pass, world, graph.view_entity());
+        Ok(())
+    }
+}
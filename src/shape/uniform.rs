@@ -0,0 +1,104 @@
+use bevy::prelude::*;
+pub use layout::{BatchUniform, MaterialUniform};
+
+// `ShaderType` emits its layout checks beside the struct rather than inside it, so they can only be allowed from an
+// enclosing module.
+#[allow(dead_code)]
+mod layout {
+    use bevy::{prelude::*, render::render_resource::ShaderType};
+
+    /// Per-batch uniform block, bound at group 1 of every shape pipeline.
+    #[derive(Copy, Clone, ShaderType)]
+    pub struct BatchUniform {
+        /// Wrapped elapsed seconds; filled in by the renderer.
+        pub time: f32,
+        /// [`layer_depth`](crate::shape::opaque::layer_depth) of opaque batches; filled in by the renderer.
+        pub depth: f32,
+        pub tint: Vec4,
+        pub params: Vec4,
+    }
+
+    /// Parameters of a [`ShapeMaterial`], bound at group 2 of pipelines drawing with it.
+    ///
+    /// [`ShapeMaterial`]: crate::shape::material::ShapeMaterial
+    #[derive(Copy, Clone, ShaderType)]
+    pub struct MaterialUniform {
+        pub params: [Vec4; MaterialUniform::MAX_PARAMS],
+    }
+}
+
+impl Default for BatchUniform {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            time: 0.0,
+            depth: 0.0,
+            tint: Vec4::ONE,
+            params: Vec4::ZERO,
+        }
+    }
+}
+
+/// The `tint` and `params` of a [`BatchUniform`], stored as bit patterns so that keys holding them can be hashed.
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+pub struct UniformParams {
+    tint: [u32; 4],
+    params: [u32; 4],
+}
+
+impl UniformParams {
+    /// White tint and zeroed params.
+    pub const DEFAULT: Self = Self {
+        tint: [0x3f80_0000; 4],
+        params: [0; 4],
+    };
+
+    #[inline]
+    pub fn new(tint: Vec4, params: Vec4) -> Self {
+        Self {
+            tint: tint.to_array().map(f32::to_bits),
+            params: params.to_array().map(f32::to_bits),
+        }
+    }
+
+    #[inline]
+    pub fn tint(self) -> Vec4 {
+        Vec4::from_array(self.tint.map(f32::from_bits))
+    }
+
+    #[inline]
+    pub fn params(self) -> Vec4 {
+        Vec4::from_array(self.params.map(f32::from_bits))
+    }
+
+    #[inline]
+    pub fn with_tint(self, tint: Vec4) -> Self {
+        Self::new(tint, self.params())
+    }
+
+    #[inline]
+    pub fn with_params(self, params: Vec4) -> Self {
+        Self::new(self.tint(), params)
+    }
+
+    #[inline]
+    pub fn uniform(self) -> BatchUniform {
+        BatchUniform {
+            time: 0.0,
+            depth: 0.0,
+            tint: self.tint(),
+            params: self.params(),
+        }
+    }
+}
+
+impl Default for UniformParams {
+    #[inline]
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl MaterialUniform {
+    pub const MAX_PARAMS: usize = 16;
+}
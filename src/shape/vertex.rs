@@ -1,16 +1,27 @@
-use std::{hash::Hash, marker::PhantomData};
+use std::{hash::Hash, marker::PhantomData, mem, ops::Range};
 
 use bevy::{
     core::Pod,
     ecs::system::{StaticSystemParam, SystemParam, SystemParamItem},
     prelude::*,
     render::{
-        render_resource::{RenderPipelineDescriptor, VertexAttribute},
+        mesh::PrimitiveTopology,
+        render_resource::{IndexFormat, RenderPipelineDescriptor, ShaderDefVal, VertexAttribute},
         Render, RenderApp,
     },
+    tasks::ComputeTaskPool,
 };
 
-use crate::shape::{pipeline::Requests, ShapeSystems};
+use crate::shape::{
+    diagnostic::{ShapeError, ShapeErrors},
+    material::ShapeMaterial,
+    pipeline::Requests,
+    primitive::Primitive,
+    retain::{Retained, RetainedBatch},
+    sdf::{SdfQueue, SdfVertex},
+    uniform::BatchUniform,
+    ShapeSystems,
+};
 
 pub struct ShaperPlugin<T: Shaper> {
     _marker: PhantomData<fn() -> T>,
@@ -23,7 +34,10 @@ impl<T: Shaper> Default for ShaperPlugin<T> {
     }
 }
 
-impl<T: Shaper> Plugin for ShaperPlugin<T> {
+impl<T: Shaper> Plugin for ShaperPlugin<T>
+where
+    for<'w, 's> SystemParamItem<'w, 's, T::DrawParam>: Sync,
+{
     fn build(&self, app: &mut App) {
         if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
             render_app
@@ -33,6 +47,10 @@ impl<T: Shaper> Plugin for ShaperPlugin<T> {
     }
 }
 
+/// Offset added to the layers of `T`'s requests when mapping them into [`Transparent2d`] sort keys. With the default of
+/// `0.0`, a request's layer matches the `Transform.z` of sprites and meshes it should be drawn between.
+///
+/// [`Transparent2d`]: bevy::core_pipeline::core_2d::Transparent2d
 #[derive(Resource)]
 pub struct DrawLayer<T: Vertex> {
     pub layer: f32,
@@ -60,21 +78,103 @@ impl<T: Vertex> Clone for DrawLayer<T> {
 impl<T: Vertex> Default for DrawLayer<T> {
     #[inline]
     fn default() -> Self {
-        Self::new(50.0)
+        Self::new(0.0)
     }
 }
 
 pub trait Vertex: Send + Sync + Pod {
     type Key: VertexKey;
+    /// Per-instance data of [`InstanceRequest`]s; `()` for vertex types that aren't drawn instanced.
+    type Instance: Send + Sync + Pod;
 
-    const SHADER: Handle<Shader>;
+    /// Default asset path of the shader, kept loaded for the lifetime of the app so it hot-reloads with `file_watcher`.
     const SHADER_SOURCE: &'static str;
 
     const LAYOUT: &'static [VertexAttribute];
+    /// Locations have to start past the ones of
+    /// [`MeshVertex::LAYOUT`](crate::shape::primitive::MeshVertex::LAYOUT). The shader is compiled with `INSTANCED`
+    /// defined when drawing instances.
+    const INSTANCE_LAYOUT: &'static [VertexAttribute] = &[];
+
+    /// World position of the vertex, used to bound requests so that [`BatchReorder`] can tell whether they overlap.
+    /// Requests of vertex types returning `None` are never reordered past each other.
+    ///
+    /// [`BatchReorder`]: crate::shape::pipeline::BatchReorder
+    #[inline]
+    fn position(&self) -> Option<Vec2> {
+        None
+    }
+
+    /// Conservative bounds of `instance` drawn as `primitive`, see [`position`](Self::position).
+    #[inline]
+    fn instance_bounds(_instance: &Self::Instance, _primitive: Primitive) -> Option<Rect> {
+        None
+    }
+
+    /// Bounds of `vertices`, if all of them have a [`position`](Self::position).
+    fn bounds(vertices: &[Self]) -> Option<Rect> {
+        let empty = Rect {
+            min: Vec2::INFINITY,
+            max: Vec2::NEG_INFINITY,
+        };
+
+        vertices.iter().try_fold(empty, |rect, vertex| {
+            let pos = vertex.position()?;
+            Some(Rect {
+                min: rect.min.min(pos),
+                max: rect.max.max(pos),
+            })
+        })
+    }
 }
 
 pub trait VertexKey: Send + Sync + Clone + Eq + PartialEq + Hash {
     fn specialize(self, desc: &mut RenderPipelineDescriptor);
+
+    /// Shader defs that both the vertex and fragment shader are compiled with.
+    #[inline]
+    fn shader_defs(&self) -> Vec<ShaderDefVal> {
+        Vec::new()
+    }
+
+    /// Uniform of the batches drawn with this key, bound at group 1. Its `time` and `depth` are filled in by the
+    /// renderer.
+    #[inline]
+    fn uniform(&self) -> BatchUniform {
+        BatchUniform::default()
+    }
+
+    /// Material whose shader replaces the fragment stage of batches drawn with this key. Batches are skipped until the
+    /// material is prepared.
+    #[inline]
+    fn material(&self) -> Option<AssetId<ShapeMaterial>> {
+        None
+    }
+
+    /// Whether batches drawn with this key go to the depth-tested [`ShapeOpaque2d`] phase instead of [`Transparent2d`].
+    /// Their shader is compiled with `OPAQUE` defined, and has to write [`BatchUniform::depth`] as clip space depth.
+    ///
+    /// [`ShapeOpaque2d`]: crate::shape::opaque::ShapeOpaque2d
+    /// [`Transparent2d`]: bevy::core_pipeline::core_2d::Transparent2d
+    #[inline]
+    fn opaque(&self) -> bool {
+        false
+    }
+
+    /// Whether the [`Vertex::position`]s of requests drawn with this key are in world space. Only then are they bounded
+    /// for [`BatchReorder`], as bounds in other spaces can't be compared to the ones of world-space requests.
+    ///
+    /// [`BatchReorder`]: crate::shape::pipeline::BatchReorder
+    #[inline]
+    fn world_space(&self) -> bool {
+        true
+    }
+
+    /// Strips everything that only affects [`uniform`](Self::uniform), so keys that differ only in it share pipelines.
+    #[inline]
+    fn pipeline_key(&self) -> Self {
+        self.clone()
+    }
 }
 
 pub trait Shaper: Component {
@@ -84,26 +184,240 @@ pub trait Shaper: Component {
 
     fn extract(param: StaticSystemParam<Self::ExtractParam>);
 
-    fn draw(&mut self, param: &mut SystemParamItem<Self::DrawParam>, out: &mut Vec<Request<Self::Vertex>>);
+    /// Called from multiple tasks of the [`ComputeTaskPool`] at once, hence the shared `param`.
+    fn draw(&mut self, param: &SystemParamItem<Self::DrawParam>, out: &mut Shapes<Self::Vertex>);
+}
+
+/// How the indices of a [`Request`] form primitives.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default)]
+pub enum Topology {
+    #[default]
+    TriangleList,
+    /// Strips may be cut with [`RESTART`](Self::RESTART).
+    TriangleStrip,
+    /// One pixel wide lines, regardless of zoom.
+    LineList,
+}
+
+impl Topology {
+    /// Index that ends a triangle strip, so the next index starts a new one. Becomes `u16::MAX` in 16-bit index
+    /// buffers.
+    pub const RESTART: u32 = u32::MAX;
+
+    #[inline]
+    pub const fn primitive_topology(self) -> PrimitiveTopology {
+        match self {
+            Self::TriangleList => PrimitiveTopology::TriangleList,
+            Self::TriangleStrip => PrimitiveTopology::TriangleStrip,
+            Self::LineList => PrimitiveTopology::LineList,
+        }
+    }
+
+    /// `format` is the format of the index buffer the strip is drawn from.
+    #[inline]
+    pub const fn strip_index_format(self, format: IndexFormat) -> Option<IndexFormat> {
+        match self {
+            Self::TriangleStrip => Some(format),
+            Self::TriangleList | Self::LineList => None,
+        }
+    }
+
+    /// Number of indices [`rebase`](Self::rebase) yields for `len` indices.
+    #[inline]
+    pub const fn index_count(self, len: usize) -> usize {
+        match self {
+            Self::TriangleStrip => len + 1,
+            Self::TriangleList | Self::LineList => len,
+        }
+    }
+
+    /// Offsets `indices` by `base`, keeping restarts as they are. Strips are terminated with a restart, so consecutive
+    /// requests may still be drawn at once.
+    #[inline]
+    pub fn rebase(self, indices: impl IntoIterator<Item = u32>, base: u32) -> impl Iterator<Item = u32> {
+        indices
+            .into_iter()
+            .map(move |i| if i == Self::RESTART { i } else { base + i })
+            .chain((self == Self::TriangleStrip).then_some(Self::RESTART))
+    }
+}
+
+/// Coordinate space of vertex positions, selected through a shader def understood by the shaders of
+/// [`DrawVertex`](crate::draw::vertex::DrawVertex) and [`SdfVertex`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub enum DrawSpace {
+    /// Transformed by the camera.
+    #[default]
+    World,
+    /// Physical pixels of the viewport, from its top left corner with Y pointing down. Window cursor positions are in
+    /// logical pixels, so have to be scaled by the window's scale factor first.
+    ScreenPixels,
+    /// Like `ScreenPixels`, but spanning `0.0..1.0` across the viewport.
+    ScreenNormalized,
+}
+
+impl DrawSpace {
+    #[inline]
+    pub const fn shader_def(self) -> Option<&'static str> {
+        match self {
+            Self::World => None,
+            Self::ScreenPixels => Some("SCREEN_PIXELS"),
+            Self::ScreenNormalized => Some("SCREEN_NORMALIZED"),
+        }
+    }
 }
 
 pub struct Request<T: Vertex> {
     pub layer: f32,
+    pub topology: Topology,
     pub vertices: Vec<T>,
     pub indices: Vec<u32>,
     pub key: T::Key,
 }
 
+/// A single instance of a [`Primitive`] mesh. Consecutive instances of the same primitive and key are drawn at once.
+pub struct InstanceRequest<T: Vertex> {
+    pub layer: f32,
+    pub primitive: Primitive,
+    pub instance: T::Instance,
+    pub key: T::Key,
+}
+
+pub struct Shapes<T: Vertex> {
+    pub requests: Vec<Request<T>>,
+    pub instances: Vec<InstanceRequest<T>>,
+    /// Analytic shapes, drawn by [`SdfVertex`]'s pipeline regardless of `T` if the
+    /// [`SdfPlugin`](crate::shape::sdf::SdfPlugin) is added.
+    pub sdf: Vec<Request<SdfVertex>>,
+}
+
+impl<T: Vertex> Shapes<T> {
+    #[inline]
+    pub fn len(&self) -> (usize, usize, usize) {
+        (self.requests.len(), self.instances.len(), self.sdf.len())
+    }
+}
+
+impl<T: Vertex> Default for Shapes<T> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            requests: Vec::new(),
+            instances: Vec::new(),
+            sdf: Vec::new(),
+        }
+    }
+}
+
+/// Ranges of requests, instances and SDF requests drawn by a retained shaper.
+type RetainedSpan = (Entity, Range<usize>, Range<usize>, Range<usize>);
+
+/// Per-task output of [`queue_drawers`], kept around to reuse its allocations.
+pub struct DrawBuffer<T: Vertex> {
+    shapes: Shapes<T>,
+    retained: Vec<RetainedSpan>,
+}
+
+impl<T: Vertex> Default for DrawBuffer<T> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            shapes: default(),
+            retained: Vec::new(),
+        }
+    }
+}
+
 pub fn queue_drawers<T: Shaper>(
-    mut query: Query<&mut T>,
+    mut query: Query<(&mut T, Option<&Retained>)>,
     param: StaticSystemParam<T::DrawParam>,
     requests: Res<Requests<T::Vertex>>,
-    mut vertices: Local<Vec<Request<T::Vertex>>>,
-) {
-    let mut param = param.into_inner();
-    for mut drawer in &mut query {
-        drawer.draw(&mut param, &mut vertices);
+    mut retained_batch: ResMut<RetainedBatch<T::Vertex>>,
+    sdf_queue: Option<Res<SdfQueue>>,
+    mut buffers: Local<Vec<DrawBuffer<T::Vertex>>>,
+    errors: Res<ShapeErrors>,
+) where
+    for<'w, 's> SystemParamItem<'w, 's, T::DrawParam>: Sync,
+{
+    let param = param.into_inner();
+    let mut retained = errors.lock(&requests.retained, "retained_requests");
+
+    // Reusing unchanged retained shapers is cheap, so only the rest is handed out to the task pool. Their SDF shapes
+    // are reused by `queue_sdf`, which alone accesses `SdfVertex`'s retained geometry.
+    let mut drawers = Vec::with_capacity(query.iter().len());
+    let mut sdf_reused = Vec::new();
+    for (drawer, retain) in &mut query {
+        if let Some(&Retained { entity, changed: false }) = retain {
+            if let Some(cached) = retained_batch.reuse(entity) {
+                retained.extend_from_slice(cached);
+                sdf_reused.push(entity);
+                continue
+            }
+        }
+
+        drawers.push((drawer, retain.map(|retain| retain.entity)));
+    }
+
+    if let Some(sdf_queue) = &sdf_queue {
+        errors.lock(&sdf_queue.reused, "sdf_reused").append(&mut sdf_reused);
+    }
+
+    if drawers.is_empty() {
+        return
     }
 
-    requests.values.lock().unwrap().append(&mut vertices);
+    let pool = ComputeTaskPool::get();
+    let chunk_size = drawers.len().div_ceil(pool.thread_num().max(1));
+    let chunk_count = drawers.len().div_ceil(chunk_size);
+    if buffers.len() < chunk_count {
+        buffers.resize_with(chunk_count, default);
+    }
+
+    pool.scope(|scope| {
+        for (chunk, buffer) in drawers.chunks_mut(chunk_size).zip(buffers.iter_mut()) {
+            let param = &param;
+            scope.spawn(async move {
+                for (drawer, entity) in chunk {
+                    let (requests, instances, sdf) = buffer.shapes.len();
+                    drawer.draw(param, &mut buffer.shapes);
+
+                    if let Some(entity) = *entity {
+                        let (requests_end, instances_end, sdf_end) = buffer.shapes.len();
+                        buffer
+                            .retained
+                            .push((entity, requests..requests_end, instances..instances_end, sdf..sdf_end));
+                    }
+                }
+            });
+        }
+    });
+
+    let mut values = errors.lock(&requests.values, "requests");
+    let mut instances = errors.lock(&requests.instances, "instance_requests");
+    let mut sdf_drawn = sdf_queue.as_ref().map(|sdf_queue| errors.lock(&sdf_queue.drawn, "sdf_drawn"));
+    for DrawBuffer { shapes, retained: spans } in &mut buffers[..chunk_count] {
+        if sdf_drawn.is_none() && !shapes.sdf.is_empty() {
+            errors.report(ShapeError::MissingSdfPlugin);
+            shapes.sdf.clear();
+        }
+
+        // Drain from the back so the remaining ranges stay valid.
+        while let Some((entity, requests, instances, sdf)) = spans.pop() {
+            retained.extend_from_slice(retained_batch.store(
+                entity,
+                shapes.requests.drain(requests),
+                shapes.instances.drain(instances),
+            ));
+
+            if let Some(sdf_drawn) = sdf_drawn.as_mut().filter(|_| !sdf.is_empty()) {
+                sdf_drawn.push((Some(entity), shapes.sdf.drain(sdf).collect()));
+            }
+        }
+
+        values.append(&mut shapes.requests);
+        instances.append(&mut shapes.instances);
+        if let Some(sdf_drawn) = sdf_drawn.as_mut().filter(|_| !shapes.sdf.is_empty()) {
+            sdf_drawn.push((None, mem::take(&mut shapes.sdf)));
+        }
+    }
 }
@@ -23,6 +23,8 @@ impl Interpolation<f32, f32> for Interp {
     }
 }
 
+/// Blends in linear RGB, the space vertex colors are in and the GPU interpolates them in, returning
+/// [`Color::RgbaLinear`].
 impl Interpolation<Color, f32> for Interp {
     #[inline]
     fn interp(self, from: Color, to: Color, progress: f32) -> Color {
@@ -37,6 +39,57 @@ impl Interpolation<Color, f32> for Interp {
     }
 }
 
+/// Blends colors in OKLab instead, keeping perceived lightness and hue steadier than linear RGB does between distant
+/// hues. Alpha is still blended linearly.
+#[derive(Copy, Clone)]
+pub struct Oklab(pub Interp);
+
+impl Interpolation<Color, f32> for Oklab {
+    #[inline]
+    fn interp(self, from: Color, to: Color, progress: f32) -> Color {
+        let [fr, fg, fb, fa] = from.as_linear_rgba_f32();
+        let [tr, tg, tb, ta] = to.as_linear_rgba_f32();
+        let (from, to) = (linear_to_oklab([fr, fg, fb]), linear_to_oklab([tr, tg, tb]));
+
+        let [red, green, blue] = oklab_to_linear([0, 1, 2].map(|i| self.0.interp(from[i], to[i], progress)));
+        Color::RgbaLinear {
+            red,
+            green,
+            blue,
+            alpha: self.0.interp(fa, ta, progress),
+        }
+    }
+}
+
+/// Linear RGB to OKLab lightness, green-red and blue-yellow.
+#[inline]
+pub fn linear_to_oklab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let l = (0.41222147 * r + 0.53633254 * g + 0.051445993 * b).cbrt();
+    let m = (0.2119035 * r + 0.6806996 * g + 0.10739696 * b).cbrt();
+    let s = (0.08830246 * r + 0.28171884 * g + 0.6299787 * b).cbrt();
+
+    [
+        0.21045426 * l + 0.7936178 * m - 0.004072047 * s,
+        1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+        0.025904037 * l + 0.78277177 * m - 0.80867577 * s,
+    ]
+}
+
+/// Inverse of [`linear_to_oklab`].
+#[inline]
+pub fn oklab_to_linear([l, a, b]: [f32; 3]) -> [f32; 3] {
+    let l_ = l + 0.39633778 * a + 0.21580376 * b;
+    let m_ = l - 0.105561346 * a - 0.06385417 * b;
+    let s_ = l - 0.08948418 * a - 1.2914855 * b;
+    let (l, m, s) = (l_ * l_ * l_, m_ * m_ * m_, s_ * s_ * s_);
+
+    [
+        4.0767417 * l - 3.3077116 * m + 0.23096994 * s,
+        -1.268438 * l + 2.6097574 * m - 0.34131938 * s,
+        -0.0041960863 * l - 0.7034186 * m + 1.7076147 * s,
+    ]
+}
+
 #[inline]
 pub fn curve(f: f32, from: f32, to: f32) -> f32 {
     if f < from {
@@ -94,3 +147,30 @@ pub fn vec_angle(angle: f32, x: f32, y: f32) -> Vec2 {
         y: x * sin + y * cos,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::util::math::{linear_to_oklab, oklab_to_linear, within};
+
+    #[test]
+    fn oklab_round_trips() {
+        for r in 0..=4 {
+            for g in 0..=4 {
+                for b in 0..=4 {
+                    let color = [r, g, b].map(|c| c as f32 / 4.0);
+                    let back = oklab_to_linear(linear_to_oklab(color));
+                    assert!(
+                        color.iter().zip(back).all(|(&a, b)| within(a, b, 1e-4)),
+                        "{color:?} != {back:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn oklab_white_is_neutral() {
+        let [l, a, b] = linear_to_oklab([1.0; 3]);
+        assert!(within(l, 1.0, 1e-4) && within(a, 0.0, 1e-4) && within(b, 0.0, 1e-4));
+    }
+}
@@ -3,9 +3,37 @@ use std::mem;
 use bevy::prelude::*;
 use fastrand::Rng;
 use float_next_after::NextAfter;
+use iyes_progress::prelude::*;
 
 pub mod math;
 
+type TrackShader = Box<dyn Fn(&mut App) + Send + Sync>;
+
+/// Shaders a plugin adds to [`AssetsLoading`], so an [`iyes_progress`] loading state waits for them. Plugins fill it
+/// from their `track_loading` builder and apply it when built.
+#[derive(Default)]
+pub struct TrackLoading(Vec<TrackShader>);
+
+impl TrackLoading {
+    /// Tracks the shader `R` holds when entering `state`.
+    pub fn shader<R: Resource>(mut self, state: impl States, handle: fn(&R) -> &Handle<Shader>) -> Self {
+        self.0.push(Box::new(move |app| {
+            app.add_systems(
+                OnEnter(state.clone()),
+                move |shader: Res<R>, mut loading: ResMut<AssetsLoading>| loading.add(handle(&shader)),
+            );
+        }));
+        self
+    }
+
+    #[inline]
+    pub fn apply(&self, app: &mut App) {
+        for track in &self.0 {
+            track(app);
+        }
+    }
+}
+
 pub trait RngExt {
     fn range_f32(&mut self, from: f32, to: f32) -> f32;
 